@@ -14,18 +14,26 @@
  * limitations under the License.
 */
 
+use crate::lock::vendor;
 use crate::Result;
 use failure::format_err;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::OsStr;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::fs;
 use std::io::Result as IoResult;
 use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Imports protoc resolves by itself, as it bundles the well-known types.
+const WELL_KNOWN_TYPES: &str = "google/protobuf/";
 
 #[derive(Debug)]
 enum ErrorCode {
     P001,
     P002,
+    P003,
+    P004,
 }
 
 #[derive(Clone)]
@@ -75,6 +83,141 @@ pub fn run_checks<P: AsRef<Path>>(
     }
 }
 
+/// Checks that every import of the protos vendored under `vendored_root` resolves to a
+/// vendored file or one under `include_roots`, and that none of them import each other
+/// in a cycle. Problems are only warned about unless `strict`.
+pub fn run_import_checks(
+    vendored_root: &Path,
+    include_roots: &[String],
+    strict: bool,
+) -> Result<()> {
+    log::info!("Checking imports of vendored protos..");
+
+    let imports = vendored_imports(vendored_root)?;
+    let mut results = check_imports_resolve(vendored_root, include_roots, &imports);
+    results.append(&mut check_no_import_cycles(vendored_root, &imports));
+
+    if !strict {
+        for result in &results {
+            log::warn!("{}", result);
+        }
+        return Ok(());
+    }
+
+    report(&results);
+
+    if !results.is_empty() {
+        Err(format_err!("Validation errors reported"))
+    } else {
+        Ok(())
+    }
+}
+
+/// The imports of each proto file under `vendored_root`, keyed by its path relative to it.
+fn vendored_imports(vendored_root: &Path) -> Result<BTreeMap<String, Vec<String>>> {
+    let mut imports = BTreeMap::new();
+    for entry in WalkDir::new(vendored_root) {
+        let entry = entry?;
+        if entry.file_type().is_file() && entry.path().extension() == Some(OsStr::new("proto")) {
+            let path = entry.path().strip_prefix(vendored_root)?;
+            let contents = fs::read_to_string(entry.path())?;
+            imports.insert(
+                path.to_string_lossy().replace('\\', "/"),
+                vendor::proto_imports(vendor::upstream_contents(&contents))
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            );
+        }
+    }
+    Ok(imports)
+}
+
+fn check_imports_resolve(
+    vendored_root: &Path,
+    include_roots: &[String],
+    imports: &BTreeMap<String, Vec<String>>,
+) -> Vec<CheckResult> {
+    let resolves = |import: &str| {
+        imports.contains_key(import)
+            || import.starts_with(WELL_KNOWN_TYPES)
+            || include_roots
+                .iter()
+                .any(|root| Path::new(root).join(import).is_file())
+    };
+
+    imports
+        .iter()
+        .flat_map(|(file, file_imports)| {
+            file_imports
+                .iter()
+                .filter(|import| !resolves(import))
+                .map(move |import| CheckResult {
+                    checked_resource: vendored_root.join(file),
+                    message: format!(
+                        "imports {} which was not vendored and is not under any include_roots",
+                        import
+                    ),
+                    error_code: &ErrorCode::P003,
+                })
+        })
+        .collect()
+}
+
+fn check_no_import_cycles(
+    vendored_root: &Path,
+    imports: &BTreeMap<String, Vec<String>>,
+) -> Vec<CheckResult> {
+    let mut cycles = BTreeSet::new();
+    let mut visited = BTreeSet::new();
+    for file in imports.keys() {
+        find_cycles(file, imports, &mut Vec::new(), &mut visited, &mut cycles);
+    }
+
+    cycles
+        .into_iter()
+        .map(|cycle: Vec<String>| CheckResult {
+            checked_resource: vendored_root.join(&cycle[0]),
+            message: format!("is part of the import cycle {}", cycle.join(" -> ")),
+            error_code: &ErrorCode::P004,
+        })
+        .collect()
+}
+
+/// Depth first search from `file` recording into `cycles` each cycle found, rotated to
+/// start and end at its smallest file so that it is only recorded once.
+fn find_cycles(
+    file: &str,
+    imports: &BTreeMap<String, Vec<String>>,
+    stack: &mut Vec<String>,
+    visited: &mut BTreeSet<String>,
+    cycles: &mut BTreeSet<Vec<String>>,
+) {
+    if let Some(start) = stack.iter().position(|f| f == file) {
+        let cycle = &stack[start..];
+        let smallest = (0..cycle.len()).min_by_key(|&i| &cycle[i]).unwrap();
+        let mut rotated: Vec<String> = cycle[smallest..]
+            .iter()
+            .chain(&cycle[..smallest])
+            .cloned()
+            .collect();
+        rotated.push(rotated[0].clone());
+        cycles.insert(rotated);
+        return;
+    }
+    if !visited.insert(file.to_owned()) {
+        return;
+    }
+
+    stack.push(file.to_owned());
+    for import in imports.get(file).into_iter().flatten() {
+        if imports.contains_key(import) {
+            find_cycles(import, imports, stack, visited, cycles);
+        }
+    }
+    stack.pop();
+}
+
 fn report(results: &[CheckResult]) {
     for result in results {
         log::error!("{}", result);
@@ -136,3 +279,63 @@ fn check_proto_directory_structure<P: AsRef<Path>>(
     };
     Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vendor_protos(protos: &[(&str, &str)]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for (path, contents) in protos {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_reports_unresolved_imports() {
+        let include_root = vendor_protos(&[("local/money.proto", "")]);
+        let root = vendor_protos(&[
+            (
+                "acme/service.proto",
+                "import \"acme/types.proto\";\
+                 \nimport \"local/money.proto\";\
+                 \nimport \"google/protobuf/timestamp.proto\";\
+                 \nimport \"acme/missing.proto\";\
+                 \n// import \"acme/commented.proto\";\
+                 \n/* import \"acme/also_commented.proto\"; */",
+            ),
+            ("acme/types.proto", ""),
+        ]);
+        let include_roots = vec![include_root.path().to_string_lossy().into_owned()];
+
+        let imports = vendored_imports(root.path()).unwrap();
+        let results = check_imports_resolve(root.path(), &include_roots, &imports);
+
+        assert_eq!(1, results.len());
+        assert!(results[0].message.contains("acme/missing.proto"));
+        assert!(run_import_checks(root.path(), &include_roots, false).is_ok());
+        assert!(run_import_checks(root.path(), &include_roots, true).is_err());
+    }
+
+    #[test]
+    fn test_reports_each_import_cycle_once() {
+        let root = vendor_protos(&[
+            ("a.proto", "import \"b.proto\";"),
+            ("b.proto", "import \"c.proto\";"),
+            ("c.proto", "import \"a.proto\";\nimport \"d.proto\";"),
+            ("d.proto", ""),
+        ]);
+
+        let imports = vendored_imports(root.path()).unwrap();
+        let results = check_no_import_cycles(root.path(), &imports);
+
+        assert_eq!(1, results.len());
+        assert!(results[0]
+            .message
+            .ends_with("a.proto -> b.proto -> c.proto -> a.proto"));
+        assert!(check_imports_resolve(root.path(), &[], &imports).is_empty());
+    }
+}
@@ -14,22 +14,41 @@
  * limitations under the License.
 */
 
+use crate::git::TrustedKey;
 use crate::git_url::GitUrl;
 use crate::util;
-use crate::Result;
+use crate::{settings, Result};
 use failure::format_err;
+use lazy_static::lazy_static;
+use regex::Regex;
 use semver::Version;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
 use std::path::Path;
 
-#[cfg(test)]
-#[path = "../tests_utils/mod.rs"]
-mod tests_utils;
-
 pub const PROTOVEND_YAML: &str = ".protovend.yml";
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// Version of the `.protovend.yml` format this protovend writes. Optional fields can be
+/// added without changing it, as files that don't use them still mean the same thing; it
+/// only changes, along with a migration, when existing files would have to be read
+/// differently.
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Standard file options whose values are enums rather than text.
+const ENUM_OPTIONS: [&str; 1] = ["optimize_for"];
+
+lazy_static! {
+    static ref IDENTIFIER_PATTERN: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+    // A built in option, or a custom one in parentheses, either followed by any fields
+    static ref OPTION_NAME_PATTERN: Regex = Regex::new(
+        r"^(?:[A-Za-z_][A-Za-z0-9_]*|\(\.?[A-Za-z_][A-Za-z0-9_]*(?:\.[A-Za-z_][A-Za-z0-9_]*)*\))(?:\.[A-Za-z_][A-Za-z0-9_]*)*$"
+    )
+    .unwrap();
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Dependency {
     pub url: GitUrl,
     pub branch: String,
@@ -37,73 +56,133 @@ pub struct Dependency {
     pub proto_paths: Vec<String>,
     pub filename_regex: String,
     pub resolve_dependency: bool,
+    #[serde(default, skip_serializing_if = "util::is_false")]
+    pub submodules: bool,
+    /// Protobuf packages to vendor files from, each either exactly or, ending in `.*`,
+    /// including its subpackages.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<String>,
+    /// Fully qualified messages or services to vendor, along with the files they need,
+    /// instead of every proto under `proto_paths`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roots: Vec<String>,
+    /// File options to set in each vendored file, or to remove when `None`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub options: BTreeMap<String, Option<OptionValue>>,
+    /// Path prefixes of the dependency's files mapped to where they're vendored instead,
+    /// with imports of them rewritten to match.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub relocate: BTreeMap<String, String>,
+    /// Path of the license file from the root of the repository, when it isn't one of
+    /// the usual `LICENSE` or `COPYING` files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license_file: Option<String>,
 }
 
-#[derive(Debug, PartialEq, Deserialize)]
-pub struct LegacyDependency {
-    pub url: GitUrl,
-    pub branch: String,
-    pub proto_dir: String,
-    pub proto_paths: Vec<String>,
+/// The value to give a file option. Text may refer to the file's `{package}` and to
+/// the `{path}` of its directory under the vendored protos, while an enum value such as
+/// `{enum: SPEED}` is written unquoted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OptionValue {
+    Bool(bool),
+    Text(String),
+    Enum {
+        #[serde(rename = "enum")]
+        value: String,
+    },
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ProtovendConfig {
+    pub version: u32,
     pub min_protovend_version: Version,
     pub vendor: Vec<Dependency>,
+    /// When set, only revisions signed by one of these keys are vendored.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_keys: Vec<TrustedKey>,
+    /// When set, dependencies may only come from these hosts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_hosts: Vec<String>,
+    /// Directories besides the vendored protos that their imports may resolve to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_roots: Vec<String>,
+    /// Whether unresolved imports and import cycles in the vendored protos fail the
+    /// command, rather than only being warned about.
+    #[serde(default, skip_serializing_if = "util::is_false")]
+    pub strict_imports: bool,
+    /// Whether vendored files start with a comment saying where they came from.
+    #[serde(default, skip_serializing_if = "util::is_false")]
+    pub provenance_headers: bool,
 }
 
+/// `.protovend.yml` as written before it recorded a version, including by releases
+/// that predate `filename_regex` and `resolve_dependency`.
 #[derive(Debug, PartialEq, Deserialize)]
-pub struct LegacyProtovendConfig {
-    pub min_protovend_version: Version,
-    pub vendor: Vec<LegacyDependency>,
+#[serde(deny_unknown_fields)]
+struct ConfigV1 {
+    min_protovend_version: Version,
+    vendor: Option<Vec<DependencyV1>>,
+    #[serde(default)]
+    trusted_keys: Vec<TrustedKey>,
+    #[serde(default)]
+    allowed_hosts: Vec<String>,
 }
 
 #[derive(Debug, PartialEq, Deserialize)]
-pub struct EmptyProtovendConfig {
-    pub min_protovend_version: Version,
-    pub vendor: (),
-}
-
-impl From<EmptyProtovendConfig> for ProtovendConfig {
-    fn from(empty: EmptyProtovendConfig) -> Self {
-        ProtovendConfig {
-            min_protovend_version: empty.min_protovend_version,
-            vendor: vec![],
-        }
-    }
+#[serde(deny_unknown_fields)]
+struct DependencyV1 {
+    url: GitUrl,
+    branch: String,
+    proto_dir: String,
+    proto_paths: Vec<String>,
+    filename_regex: Option<String>,
+    #[serde(default)]
+    resolve_dependency: bool,
+    #[serde(default)]
+    submodules: bool,
 }
 
-impl From<LegacyProtovendConfig> for ProtovendConfig {
-    fn from(legacy_config: LegacyProtovendConfig) -> Self {
+impl From<ConfigV1> for ProtovendConfig {
+    fn from(config: ConfigV1) -> Self {
         ProtovendConfig {
-            min_protovend_version: legacy_config.min_protovend_version,
-            vendor: legacy_config.vendor.into_iter().map(|d| d.into()).collect(),
+            version: CONFIG_VERSION,
+            min_protovend_version: config.min_protovend_version,
+            vendor: config
+                .vendor
+                .unwrap_or_default()
+                .into_iter()
+                .map(|d| d.into())
+                .collect(),
+            trusted_keys: config.trusted_keys,
+            allowed_hosts: config.allowed_hosts,
+            include_roots: Vec::new(),
+            strict_imports: false,
+            provenance_headers: false,
         }
     }
 }
 
-impl From<LegacyDependency> for Dependency {
-    fn from(dep: LegacyDependency) -> Self {
+impl From<DependencyV1> for Dependency {
+    fn from(dep: DependencyV1) -> Self {
         Dependency {
-            url: dep.url.clone(),
+            url: dep.url,
             branch: dep.branch,
             proto_dir: dep.proto_dir,
             proto_paths: dep.proto_paths,
-            filename_regex: String::from(".*"),
-            resolve_dependency: false,
+            filename_regex: dep.filename_regex.unwrap_or_else(|| String::from(".*")),
+            resolve_dependency: dep.resolve_dependency,
+            submodules: dep.submodules,
+            packages: Vec::new(),
+            roots: Vec::new(),
+            options: BTreeMap::new(),
+            relocate: BTreeMap::new(),
+            license_file: None,
         }
     }
 }
 
-#[derive(Deserialize)]
-#[serde(untagged)]
-enum Config {
-    Current(ProtovendConfig),
-    Legacy(LegacyProtovendConfig),
-    Empty(EmptyProtovendConfig),
-}
-
 impl ProtovendConfig {
     pub fn write(&mut self) -> Result<()> {
         let f = File::create(Path::new(PROTOVEND_YAML))?;
@@ -111,6 +190,7 @@ impl ProtovendConfig {
         serde_yaml::to_writer(f, &self).map_err(|e| e.into())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn add_dependency(
         &mut self,
         url: GitUrl,
@@ -119,7 +199,10 @@ impl ProtovendConfig {
         proto_path: String,
         filename_regex: String,
         resolve_dependency: bool,
+        submodules: bool,
     ) -> Result<()> {
+        self.check_host(&url)?;
+        check_paths(&url, &proto_dir, std::slice::from_ref(&proto_path))?;
         let existing_dep = self.vendor.iter_mut().find(|dep| dep.url == url);
 
         match existing_dep {
@@ -144,6 +227,10 @@ impl ProtovendConfig {
                         resolve_dependency
                     )
                 }
+                if dep.submodules != submodules {
+                    dep.submodules = submodules;
+                    log::info!("Updated {} to use submodules {}", url, submodules)
+                }
                 if dep.proto_paths.contains(&proto_path) {
                     self.write().map(|_| {
                         log::info!(
@@ -167,6 +254,12 @@ impl ProtovendConfig {
                     proto_paths: vec![proto_path],
                     filename_regex,
                     resolve_dependency,
+                    submodules,
+                    packages: Vec::new(),
+                    roots: Vec::new(),
+                    options: BTreeMap::new(),
+                    relocate: BTreeMap::new(),
+                    license_file: None,
                 };
                 self.vendor.push(new);
                 self.write()
@@ -174,6 +267,118 @@ impl ProtovendConfig {
             }
         }
     }
+
+    /// Fails unless the host of `url` is allowed by both this project's `allowed_hosts` and
+    /// the machine's, or `--allow-any-host` was given.
+    pub fn check_host(&self, url: &GitUrl) -> Result<()> {
+        let settings = settings::current();
+        let host = url.host();
+        let policies = [
+            (PROTOVEND_YAML, &self.allowed_hosts),
+            ("protovend settings", &settings.allowed_hosts),
+        ];
+
+        for (source, allowed_hosts) in policies.iter() {
+            if allowed_hosts.is_empty() || allowed_hosts.iter().any(|p| host_matches(p, &host)) {
+                continue;
+            }
+            if settings.allow_any_host {
+                log::warn!(
+                    "Vendoring {} although {} is not in allowed_hosts in {}",
+                    url,
+                    host,
+                    source
+                );
+                continue;
+            }
+            return Err(format_err!(
+                "Cannot vendor {}: {} is not in allowed_hosts in {} ({}). Pass --allow-any-host to vendor from it anyway",
+                url,
+                host,
+                source,
+                allowed_hosts.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Like `check_host`, for a URL such as a submodule's that may be a local path, so
+    /// is only parsed when some `allowed_hosts` apply to it.
+    pub fn check_url(&self, url: &str) -> Result<()> {
+        let settings = settings::current();
+        if settings.allow_any_host
+            || (self.allowed_hosts.is_empty() && settings.allowed_hosts.is_empty())
+        {
+            return Ok(());
+        }
+        self.check_host(&url.parse()?)
+    }
+}
+
+/// Fails if `proto_dir` or any of `proto_paths` could lead outside the dependency.
+fn check_paths(url: &GitUrl, proto_dir: &str, proto_paths: &[String]) -> Result<()> {
+    util::safe_relative_path(proto_dir)
+        .map_err(|e| format_err!("Invalid proto_dir for {}. {}", url, e))?;
+    for proto_path in proto_paths {
+        util::safe_relative_path(proto_path)
+            .map_err(|e| format_err!("Invalid proto_path for {}. {}", url, e))?;
+    }
+    Ok(())
+}
+
+/// Fails if any of `relocate` would vendor files outside the vendored proto directory.
+fn check_relocations(url: &GitUrl, relocate: &BTreeMap<String, String>) -> Result<()> {
+    for (from, to) in relocate {
+        util::safe_relative_path(to)
+            .map_err(|e| format_err!("Invalid relocation of {} for {}. {}", from, url, e))?;
+    }
+    Ok(())
+}
+
+/// Fails if any of `options` isn't named like an option, would write something other
+/// than a value into the files, or would quote the value of an enum option.
+fn check_options(url: &GitUrl, options: &BTreeMap<String, Option<OptionValue>>) -> Result<()> {
+    for (name, value) in options {
+        if !OPTION_NAME_PATTERN.is_match(name) {
+            return Err(format_err!(
+                "Invalid option {} for {}. Option names are identifiers, or custom options in parentheses like (acme.field)",
+                name,
+                url
+            ));
+        }
+        match value {
+            Some(OptionValue::Enum { value }) if !IDENTIFIER_PATTERN.is_match(value) => {
+                return Err(format_err!(
+                    "Invalid option {} for {}. {} is not an enum value",
+                    name,
+                    url,
+                    value
+                ))
+            }
+            Some(OptionValue::Text(value)) if ENUM_OPTIONS.contains(&name.as_str()) => {
+                return Err(format_err!(
+                    "Invalid option {} for {}. It takes an enum value, write it as {{enum: {}}}",
+                    name,
+                    url,
+                    value
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether `host` is `pattern`, or a subdomain of it when `pattern` starts with `*.`.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let host = host.to_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .map_or(false, |subdomain| subdomain.ends_with('.')),
+        None => host == pattern,
+    }
 }
 
 pub fn init() -> Result<()> {
@@ -183,8 +388,14 @@ pub fn init() -> Result<()> {
         Ok(())
     } else {
         let mut config = ProtovendConfig {
+            version: CONFIG_VERSION,
             min_protovend_version: crate::CRATE_VERSION.clone(),
             vendor: Vec::new(),
+            trusted_keys: Vec::new(),
+            allowed_hosts: Vec::new(),
+            include_roots: Vec::new(),
+            strict_imports: false,
+            provenance_headers: false,
         };
         config
             .write()
@@ -196,18 +407,42 @@ pub fn get_config() -> Result<ProtovendConfig> {
     load_config(Path::new(PROTOVEND_YAML))
 }
 
+/// Rewrites `.protovend.yml` in the current format if it was written in an older one.
+pub fn migrate() -> Result<()> {
+    let config_file = Path::new(PROTOVEND_YAML);
+    let mut config = load_config(config_file)?;
+    let contents = fs::read_to_string(config_file)?;
+    let version = util::file_version(config_file, &contents, CONFIG_VERSION)?;
+    if version == CONFIG_VERSION {
+        log::info!("{} is already version {}", PROTOVEND_YAML, version);
+        Ok(())
+    } else {
+        config.write().map(|_| {
+            log::info!(
+                "Migrated {} from version {} to {}",
+                PROTOVEND_YAML,
+                version,
+                CONFIG_VERSION
+            )
+        })
+    }
+}
+
 fn load_config(config_file: &Path) -> Result<ProtovendConfig> {
     if config_file.is_file() {
-        let f = File::open(config_file)?;
-        let config: Config = serde_yaml::from_reader(f)?;
-
-        let config: ProtovendConfig = match config {
-            Config::Current(p) => p,
-            Config::Legacy(l) => l.into(),
-            Config::Empty(e) => e.into(),
-        };
+        let config = parse_config(config_file)?;
 
         if util::is_valid_version(&config.min_protovend_version) {
+            for dep in config.vendor.iter() {
+                config.check_host(&dep.url)?;
+                check_paths(&dep.url, &dep.proto_dir, &dep.proto_paths)?;
+                check_options(&dep.url, &dep.options)?;
+                check_relocations(&dep.url, &dep.relocate)?;
+                if let Some(license_file) = &dep.license_file {
+                    util::safe_relative_path(license_file)
+                        .map_err(|e| format_err!("Invalid license_file for {}. {}", dep.url, e))?;
+                }
+            }
             Ok(config)
         } else {
             Err(format_err!("protovend cli version {} is too old for included metadata files. Minimum version must be {}", *crate::CRATE_VERSION, config.min_protovend_version))
@@ -219,9 +454,24 @@ fn load_config(config_file: &Path) -> Result<ProtovendConfig> {
     }
 }
 
+/// Reads `config_file` in whichever version it was written, migrating each older
+/// version to the next in turn.
+fn parse_config(config_file: &Path) -> Result<ProtovendConfig> {
+    let contents = fs::read_to_string(config_file)?;
+    let invalid = |e| format_err!("Invalid {}: {}", config_file.display(), e);
+
+    match util::file_version(config_file, &contents, CONFIG_VERSION)? {
+        1 => serde_yaml::from_str::<ConfigV1>(&contents)
+            .map(ProtovendConfig::from)
+            .map_err(invalid),
+        _ => serde_yaml::from_str(&contents).map_err(invalid),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tests_utils;
 
     #[test]
     fn test_correctly_parses_config() {
@@ -240,6 +490,7 @@ mod tests {
             tests_utils::fs::write_contents_to_temp_file(config_contents, "protovend_config");
 
         let expected_config = ProtovendConfig {
+            version: CONFIG_VERSION,
             min_protovend_version: "0.1.8".parse::<Version>().unwrap(),
             vendor: vec![Dependency {
                 url: "git@github.skyscannertools.net:cell-placement/cell-metadata-service.git"
@@ -250,7 +501,18 @@ mod tests {
                 proto_paths: vec![String::from("path/to")],
                 filename_regex: String::from("^(a|b)"),
                 resolve_dependency: true,
+                submodules: false,
+                packages: Vec::new(),
+                roots: Vec::new(),
+                options: BTreeMap::new(),
+                relocate: BTreeMap::new(),
+                license_file: None,
             }],
+            trusted_keys: Vec::new(),
+            allowed_hosts: Vec::new(),
+            include_roots: Vec::new(),
+            strict_imports: false,
+            provenance_headers: false,
         };
 
         let actual_config = load_config(&config_path).unwrap();
@@ -268,8 +530,14 @@ mod tests {
             tests_utils::fs::write_contents_to_temp_file(config_contents, "empty_config");
 
         let expected_config = ProtovendConfig {
+            version: CONFIG_VERSION,
             min_protovend_version: "0.1.8".parse::<Version>().unwrap(),
             vendor: vec![],
+            trusted_keys: Vec::new(),
+            allowed_hosts: Vec::new(),
+            include_roots: Vec::new(),
+            strict_imports: false,
+            provenance_headers: false,
         };
 
         let actual_config = load_config(&config_path).unwrap();
@@ -279,18 +547,293 @@ mod tests {
 
     #[test]
     fn test_config_from_empty_config() {
-        let legacy_config = EmptyProtovendConfig {
+        let legacy_config = ConfigV1 {
             min_protovend_version: "0.1.8".parse::<Version>().unwrap(),
-            vendor: (),
+            vendor: None,
+            trusted_keys: Vec::new(),
+            allowed_hosts: Vec::new(),
         };
 
         let expected_config = ProtovendConfig {
+            version: CONFIG_VERSION,
             min_protovend_version: "0.1.8".parse::<Version>().unwrap(),
             vendor: vec![],
+            trusted_keys: Vec::new(),
+            allowed_hosts: Vec::new(),
+            include_roots: Vec::new(),
+            strict_imports: false,
+            provenance_headers: false,
         };
 
         let actual_config = ProtovendConfig::from(legacy_config);
 
         assert_eq!(expected_config, actual_config);
     }
+
+    #[test]
+    fn test_migrates_legacy_config() {
+        let config_contents = "--- \
+                               \nmin_protovend_version: 0.1.8 \
+                               \nvendor: \
+                               \n  - url: https://github.com/user/project.git \
+                               \n    branch: master \
+                               \n    proto_dir: proto \
+                               \n    proto_paths: \
+                               \n      - path/to";
+
+        let config_path =
+            tests_utils::fs::write_contents_to_temp_file(config_contents, "legacy_config");
+
+        let actual_config = load_config(&config_path).unwrap();
+
+        assert_eq!(CONFIG_VERSION, actual_config.version);
+        assert_eq!(".*", actual_config.vendor[0].filename_regex);
+        assert!(!actual_config.vendor[0].resolve_dependency);
+    }
+
+    #[test]
+    fn test_refuses_unknown_fields_in_legacy_config() {
+        let config_contents = "--- \
+                               \nmin_protovend_version: 0.1.8 \
+                               \nvendor: \
+                               \n  - url: https://github.com/user/project.git \
+                               \n    branch: master \
+                               \n    proto_dir: proto \
+                               \n    proto_paths: \
+                               \n      - path/to \
+                               \n    filname_regex: \".*\"";
+
+        let config_path =
+            tests_utils::fs::write_contents_to_temp_file(config_contents, "legacy_typo_config");
+
+        let error = load_config(&config_path).err().unwrap().to_string();
+
+        assert!(error.contains("unknown field `filname_regex`"), "{}", error);
+    }
+
+    #[test]
+    fn test_invalid_config_names_line_and_field() {
+        let config_contents = "--- \
+             \nversion: 2 \
+             \nmin_protovend_version: 0.1.8 \
+             \nvendor: \
+             \n  - url: https://github.com/user/project.git \
+             \n    branch: master \
+             \n    proto_dir: proto \
+             \n    proto_paths: \
+             \n      - path/to \
+             \n    filname_regex: \".*\"\
+             \n    resolve_dependency: false";
+
+        let config_path =
+            tests_utils::fs::write_contents_to_temp_file(config_contents, "invalid_config");
+
+        let error = load_config(&config_path).err().unwrap().to_string();
+
+        assert!(error.contains("unknown field `filname_regex`"), "{}", error);
+        assert!(error.contains("line 10"), "{}", error);
+    }
+
+    #[test]
+    fn test_refuses_newer_config_versions() {
+        let config_contents = "--- \
+                               \nversion: 99 \
+                               \nmin_protovend_version: 0.1.8 \
+                               \nvendor: []";
+
+        let config_path =
+            tests_utils::fs::write_contents_to_temp_file(config_contents, "newer_config");
+
+        let error = load_config(&config_path).err().unwrap();
+
+        assert!(error.to_string().contains("version 99"), "{}", error);
+    }
+
+    #[test]
+    fn test_correctly_parses_trusted_keys() {
+        let config_contents = "--- \
+                               \nmin_protovend_version: 0.1.8 \
+                               \nvendor: \
+                               \ntrusted_keys: \
+                               \n  - DD23838187B5DAAE6379C99188F034F0254424D9 \
+                               \n  - ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIA ci@example.com";
+
+        let config_path =
+            tests_utils::fs::write_contents_to_temp_file(config_contents, "trusted_keys_config");
+
+        let actual_config = load_config(&config_path).unwrap();
+
+        assert_eq!(
+            vec![
+                TrustedKey::Gpg(String::from("DD23838187B5DAAE6379C99188F034F0254424D9")),
+                TrustedKey::Ssh(String::from(
+                    "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIA ci@example.com"
+                )),
+            ],
+            actual_config.trusted_keys
+        );
+    }
+
+    #[test]
+    fn test_host_patterns() {
+        assert!(host_matches("github.com", "github.com"));
+        assert!(host_matches("GitHub.com", "github.COM"));
+        assert!(!host_matches("github.com", "gist.github.com"));
+        assert!(host_matches("*.example.com", "git.example.com"));
+        assert!(host_matches("*.example.com", "a.b.example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(!host_matches("*.example.com", "notexample.com"));
+    }
+
+    #[test]
+    fn test_refuses_hosts_not_allowed() {
+        let config_contents = "--- \
+             \nmin_protovend_version: 0.1.8 \
+             \nvendor: \
+             \n  - url: https://gitlab.com/user/project.git \
+             \n    branch: master \
+             \n    proto_dir: proto \
+             \n    proto_paths: \
+             \n      - path/to \
+             \n    filename_regex: \".*\"\
+             \n    resolve_dependency: false \
+             \nallowed_hosts: \
+             \n  - github.com";
+
+        let config_path =
+            tests_utils::fs::write_contents_to_temp_file(config_contents, "allowed_hosts_config");
+
+        let error = load_config(&config_path).err().unwrap();
+        assert!(error
+            .to_string()
+            .contains("gitlab.com is not in allowed_hosts"));
+
+        let mut config = ProtovendConfig {
+            version: CONFIG_VERSION,
+            min_protovend_version: "0.1.8".parse::<Version>().unwrap(),
+            vendor: vec![],
+            trusted_keys: Vec::new(),
+            allowed_hosts: vec![String::from("github.com")],
+            include_roots: Vec::new(),
+            strict_imports: false,
+            provenance_headers: false,
+        };
+        let allowed = "https://github.com/user/project.git".parse().unwrap();
+        let refused = "git@gitlab.com:user/project.git".parse().unwrap();
+
+        assert!(config.check_host(&allowed).is_ok());
+        assert!(config
+            .add_dependency(
+                refused,
+                String::from("master"),
+                String::from("proto"),
+                String::from("path/to"),
+                String::from(".*"),
+                false,
+                false
+            )
+            .is_err());
+
+        assert!(config.check_url("git@github.com:user/sub.git").is_ok());
+        assert!(config.check_url("https://gitlab.com/user/sub.git").is_err());
+        assert!(config.check_url("/srv/git/sub").is_err());
+        config.allowed_hosts.clear();
+        assert!(config.check_url("/srv/git/sub").is_ok());
+    }
+
+    #[test]
+    fn test_refuses_paths_outside_the_dependency() {
+        let mut config = ProtovendConfig {
+            version: CONFIG_VERSION,
+            min_protovend_version: "0.1.8".parse::<Version>().unwrap(),
+            vendor: vec![],
+            trusted_keys: Vec::new(),
+            allowed_hosts: Vec::new(),
+            include_roots: Vec::new(),
+            strict_imports: false,
+            provenance_headers: false,
+        };
+
+        for (proto_dir, proto_path) in &[("../..", "path/to"), ("proto", "/etc")] {
+            let error = config
+                .add_dependency(
+                    "https://github.com/user/project.git".parse().unwrap(),
+                    String::from("master"),
+                    String::from(*proto_dir),
+                    String::from(*proto_path),
+                    String::from(".*"),
+                    false,
+                    false,
+                )
+                .err()
+                .unwrap();
+            assert!(error.to_string().starts_with("Invalid proto_"), "{}", error);
+        }
+        assert!(config.vendor.is_empty());
+    }
+
+    #[test]
+    fn test_refuses_relocations_outside_the_vendored_protos() {
+        let url = "https://github.com/user/project.git".parse().unwrap();
+        let relocate = |to: &str| {
+            let mut relocate = BTreeMap::new();
+            relocate.insert(String::from("acme/api"), String::from(to));
+            relocate
+        };
+
+        assert!(check_relocations(&url, &relocate("vendor/acme/api")).is_ok());
+        for to in &["../acme/api", "/acme/api"] {
+            let error = check_relocations(&url, &relocate(to)).err().unwrap();
+            assert!(
+                error
+                    .to_string()
+                    .starts_with("Invalid relocation of acme/api"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_refuses_options_that_are_not_values() {
+        let url = "https://github.com/user/project.git".parse().unwrap();
+        let options = |yaml: &str| serde_yaml::from_str(yaml).unwrap();
+
+        assert!(check_options(&url, &options("optimize_for: {enum: LITE_RUNTIME}")).is_ok());
+        assert!(check_options(&url, &options("java_package: com.example")).is_ok());
+        for yaml in &[
+            "optimize_for: SPEED",
+            "optimize_for: {enum: \"SPEED; option x = 1\"}",
+        ] {
+            let error = check_options(&url, &options(yaml)).err().unwrap();
+            assert!(
+                error.to_string().starts_with("Invalid option optimize_for"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_refuses_option_names_that_are_not_names() {
+        let url = "https://github.com/user/project.git".parse().unwrap();
+        let options = |yaml: &str| serde_yaml::from_str(yaml).unwrap();
+
+        for yaml in &[
+            "java_package: com.example",
+            "(acme.api.owner): team",
+            "(.acme.api.owner).name: team",
+        ] {
+            assert!(check_options(&url, &options(yaml)).is_ok(), "{}", yaml);
+        }
+        for yaml in &[
+            "\"java_package = \\\"x\\\"; import \\\"evil.proto\\\"; option y\": z",
+            "\"2fast\": z",
+            "\"(acme.api\": z",
+            "\"java package\": z",
+        ] {
+            let error = check_options(&url, &options(yaml)).err().unwrap();
+            assert!(error.to_string().starts_with("Invalid option"), "{}", error);
+        }
+    }
 }
@@ -14,21 +14,24 @@
  * limitations under the License.
 */
 
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
 use serde::de::{self, Visitor};
 use serde::{Deserializer, Serializer};
 use std::fmt::{self, Formatter};
 
-const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f";
+const LEGACY_DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f";
 
-pub fn serialize<S>(dt: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error>
+/// Writes `dt` as RFC 3339, to the second.
+pub fn serialize<S>(dt: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    serializer.serialize_str(&dt.format(DATE_FORMAT).to_string())
+    serializer.serialize_str(&dt.to_rfc3339_opts(SecondsFormat::Secs, true))
 }
 
-pub fn deserialize<'de, D>(d: D) -> Result<NaiveDateTime, D::Error>
+/// Reads RFC 3339 timestamps, or ones in the default Python format as written by
+/// older releases. Those were in local time but are taken to be UTC.
+pub fn deserialize<'de, D>(d: D) -> Result<DateTime<Utc>, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -38,17 +41,25 @@ where
 struct DateCompatVisitor;
 
 impl<'de> Visitor<'de> for DateCompatVisitor {
-    type Value = NaiveDateTime;
+    type Value = DateTime<Utc>;
 
     fn expecting(&self, formatter: &mut Formatter) -> fmt::Result {
-        write!(formatter, "a timestamp in the default Python format")
+        write!(
+            formatter,
+            "an RFC 3339 timestamp or one in the default Python format"
+        )
     }
 
-    fn visit_str<E>(self, value: &str) -> Result<NaiveDateTime, E>
+    fn visit_str<E>(self, value: &str) -> Result<DateTime<Utc>, E>
     where
         E: de::Error,
     {
-        NaiveDateTime::parse_from_str(value, DATE_FORMAT)
+        DateTime::parse_from_rfc3339(value)
+            .map(|dt| dt.with_timezone(&Utc))
+            .or_else(|_| {
+                NaiveDateTime::parse_from_str(value, LEGACY_DATE_FORMAT)
+                    .map(|dt| DateTime::from_utc(dt, Utc))
+            })
             .map_err(|_| E::custom(format!("value cannot be parsed: {}", value)))
     }
 }
@@ -15,12 +15,22 @@
 */
 
 use crate::git_url::GitUrl;
-use crate::{util, Result};
-use git2::{build::CheckoutBuilder, Oid, Repository, ResetType};
+use crate::{settings, util, Result};
+use failure::{format_err, Error};
+use git2::{build::CheckoutBuilder, Oid, Repository, ResetType, Submodule};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+mod backend;
 mod commands;
+mod error;
+mod library;
+mod signature;
+
+pub use backend::GitBackend;
+pub use error::GitError;
+pub use signature::TrustedKey;
 
 pub fn get_latest_commit_sha(url: &GitUrl, branch_name: &str) -> Result<Oid> {
     log::info!(
@@ -33,7 +43,21 @@ pub fn get_latest_commit_sha(url: &GitUrl, branch_name: &str) -> Result<Oid> {
     Ok(commit.id())
 }
 
+pub fn get_default_branch(url: &GitUrl) -> Result<String> {
+    log::info!("Looking up default branch of {}", url);
+    default_branch(&remote_url(url))?.ok_or_else(|| {
+        format_err!(
+            "{} does not advertise a default branch, please pass one with --branch",
+            url
+        )
+    })
+}
+
 pub fn get_repo(url: &GitUrl, branch: &str, revision: &str) -> Result<Repository> {
+    checkout(url, branch, revision).map_err(|error| with_default_branch(url, error))
+}
+
+fn checkout(url: &GitUrl, branch: &str, revision: &str) -> Result<Repository> {
     let destination_path = get_destination_path(url);
 
     if destination_path.exists() {
@@ -43,10 +67,10 @@ pub fn get_repo(url: &GitUrl, branch: &str, revision: &str) -> Result<Repository
             branch,
             revision
         );
-        reset_local_repo_to_commit(&destination_path, branch, revision)
+        reset_local_repo_to_commit(&destination_path, url, branch, revision)
     } else {
         log::debug!("Cloning {} to {}", url, destination_path.display());
-        let repo = clone(url.as_str(), &destination_path, branch)?; //FIXME push GitUrl further down
+        let repo = clone(&remote_url(url), &destination_path, branch)?;
         {
             let commit = repo.revparse_single(revision)?;
             repo.reset(&commit, ResetType::Hard, None)?;
@@ -55,6 +79,171 @@ pub fn get_repo(url: &GitUrl, branch: &str, revision: &str) -> Result<Repository
     }
 }
 
+/// Points at the remote's default branch when the requested one has gone, which is
+/// usually a `master` to `main` rename.
+fn with_default_branch(url: &GitUrl, error: Error) -> Error {
+    match error.downcast::<GitError>() {
+        Ok(GitError::BranchNotFound {
+            url: redacted_url,
+            branch,
+            stderr,
+            ..
+        }) => {
+            let default_branch = default_branch(&remote_url(url))
+                .ok()
+                .and_then(|default_branch| default_branch)
+                .filter(|default_branch| *default_branch != branch);
+            GitError::BranchNotFound {
+                url: redacted_url,
+                branch,
+                default_branch,
+                stderr,
+            }
+            .into()
+        }
+        Ok(other) => other.into(),
+        Err(error) => error,
+    }
+}
+
+/// The URL to connect to for `url`, after applying any rewrites from settings.
+fn remote_url(url: &GitUrl) -> String {
+    let remote_url = settings::current().rewrite_url(url.remote_url());
+    if remote_url != url.remote_url() {
+        log::debug!(
+            "Connecting to {} for {}",
+            util::redact_credentials(&remote_url),
+            url
+        );
+    }
+    remote_url
+}
+
+/// Checks out the submodules of `repo` under `proto_dir` at the commits recorded by its
+/// current revision, returning the commit each submodule path ends up at. Each submodule's
+/// URL must pass `check_url` before it is fetched.
+pub fn update_submodules<F>(
+    repo: &Repository,
+    proto_dir: &str,
+    check_url: F,
+) -> Result<BTreeMap<String, String>>
+where
+    F: Fn(&str) -> Result<()>,
+{
+    let repo_path = repo.workdir().unwrap(); //Can unwrap safely as repository is not bare
+    let network = settings::current().git.fetch;
+    let mut commits = BTreeMap::new();
+
+    for mut submodule in submodules_under(repo, proto_dir)? {
+        // Re-initialising resolves relative URLs against origin and drops earlier rewrites
+        submodule.init(true)?;
+        let key = format!("submodule.{}.url", submodule.name().unwrap_or_default());
+        let url = repo.config()?.get_string(&key)?;
+        check_url(&url)?;
+        let remote_url = settings::current().rewrite_url(&url);
+        if remote_url != url {
+            log::debug!(
+                "Connecting to {} for submodule {}",
+                util::redact_credentials(&remote_url),
+                util::redact_credentials(&url)
+            );
+        }
+        // Like origin of the clone, keep credentials out of the submodule's config; the
+        // backend is given them directly
+        repo.config()?
+            .set_str(&key, &util::strip_credentials(&remote_url))?;
+
+        let path = submodule.path().to_owned();
+        log::info!("Checking out submodule {}", path.display());
+        backend::with_retries(&network, || {
+            backend::current().update_submodule(repo_path, &path, &remote_url, network.timeout())
+        })?;
+
+        let commit = submodule.open()?.head()?.peel_to_commit()?.id();
+        commits.insert(path.to_string_lossy().into_owned(), commit.to_string());
+    }
+
+    Ok(commits)
+}
+
+/// The commits `revision` of the cached clone of `url` records for its submodules under
+/// `proto_dir`, without fetching them.
+pub fn get_submodule_commits(
+    url: &GitUrl,
+    revision: &str,
+    proto_dir: &str,
+) -> Result<BTreeMap<String, String>> {
+    let repo = Repository::open(get_destination_path(url))?;
+    let tree = repo.revparse_single(revision)?.peel_to_tree()?;
+
+    let commits = submodules_under(&repo, proto_dir)?
+        .iter()
+        .map(|submodule| {
+            let commit = tree.get_path(submodule.path())?.id();
+            Ok((
+                submodule.path().to_string_lossy().into_owned(),
+                commit.to_string(),
+            ))
+        })
+        .collect();
+    commits
+}
+
+/// Submodules inside `proto_dir`, or that `proto_dir` is itself inside.
+fn submodules_under<'r>(repo: &'r Repository, proto_dir: &str) -> Result<Vec<Submodule<'r>>> {
+    let proto_dir = Path::new(proto_dir);
+    Ok(repo
+        .submodules()?
+        .into_iter()
+        .filter(|submodule| {
+            submodule.path().starts_with(proto_dir) || proto_dir.starts_with(submodule.path())
+        })
+        .collect())
+}
+
+/// Fails unless `commit` of the cached clone of `url`, or `branch` when it is a tag
+/// pointing at `commit`, is signed by one of `keys`. Anything goes when `keys` is empty.
+pub fn verify_signature(
+    url: &GitUrl,
+    branch: &str,
+    commit: &str,
+    keys: &[TrustedKey],
+) -> Result<()> {
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    let repo = Repository::open(get_destination_path(url))?;
+    let commit = Oid::from_str(commit)?;
+
+    // A signed tag vouches for the commit it points at
+    let tag = [
+        format!("refs/remotes/origin/{}", branch),
+        format!("refs/tags/{}", branch),
+    ]
+    .iter()
+    .filter_map(|name| repo.find_reference(name).ok()?.peel_to_tag().ok())
+    .find(|tag| tag.target_id() == commit);
+    if let Some(tag) = tag {
+        if let Ok(key) = signature::verify(&repo, tag.id(), true, keys) {
+            log::info!("Tag {} of {} is signed by {}", branch, url, key);
+            return Ok(());
+        }
+    }
+
+    let key = signature::verify(&repo, commit, false, keys)
+        .map_err(|e| format_err!("Refusing to vendor {}: {}", url, e))?;
+    log::info!("Commit {} of {} is signed by {}", commit, url, key);
+    Ok(())
+}
+
+/// Whether `commit` descends from `ancestor` in the cached clone of `url`, which must
+/// already hold both.
+pub fn is_descendant(url: &GitUrl, commit: &str, ancestor: &str) -> Result<bool> {
+    let repo = Repository::open(get_destination_path(url))?;
+    Ok(repo.graph_descendant_of(Oid::from_str(commit)?, Oid::from_str(ancestor)?)?)
+}
+
 fn get_destination_path(url: &GitUrl) -> PathBuf {
     let host = util::to_alpha_num(&url.host());
     let mut destination_path = crate::REPOS_CACHE_DIRECTORY.clone();
@@ -64,14 +253,46 @@ fn get_destination_path(url: &GitUrl) -> PathBuf {
 }
 
 fn clone<P: AsRef<Path>>(url: &str, clone_dir: P, branch: &str) -> Result<Repository> {
-    fs::create_dir_all(&clone_dir)?;
-    commands::clone(&clone_dir, url, branch)?;
-    let repo = Repository::open(&clone_dir)?;
+    let network = settings::current().git.clone;
+    let clone_dir = clone_dir.as_ref();
+
+    let result = backend::with_retries(&network, || {
+        // A failed or killed attempt can leave a partial checkout behind
+        if clone_dir.exists() {
+            fs::remove_dir_all(clone_dir)?;
+        }
+        fs::create_dir_all(clone_dir)?;
+        backend::current().clone_repo(url, clone_dir, branch, network.timeout())
+    });
+
+    if let Err(error) = result {
+        // Don't leave an empty directory behind to be mistaken for a cached clone
+        fs::remove_dir_all(clone_dir)?;
+        return Err(error);
+    }
+    let repo = Repository::open(clone_dir)?;
+    // Keep credentials out of the cached clone's config; fetches are given the URL directly
+    repo.remote_set_url("origin", &util::strip_credentials(url))?;
     Ok(repo)
 }
 
+fn fetch(repo_path: &Path, url: &str, branch: &str) -> Result<()> {
+    let network = settings::current().git.fetch;
+    backend::with_retries(&network, || {
+        backend::current().fetch(repo_path, url, branch, network.timeout())
+    })
+}
+
+fn default_branch(url: &str) -> Result<Option<String>> {
+    let network = settings::current().git.ls_remote;
+    backend::with_retries(&network, || {
+        backend::current().default_branch(url, network.timeout())
+    })
+}
+
 fn reset_local_repo_to_commit<P: AsRef<Path>>(
     repo_path: P,
+    url: &GitUrl,
     branch: &str,
     revision: &str,
 ) -> Result<Repository> {
@@ -81,7 +302,7 @@ fn reset_local_repo_to_commit<P: AsRef<Path>>(
     repo.remote_add_fetch("origin", "+refs/heads/*:refs/remotes/origin/*")?;
 
     // Pull updates for the relevant branch
-    commands::fetch(repo_path, branch, "origin")?;
+    fetch(repo_path.as_ref(), &remote_url(url), branch)?;
 
     let branch = &format!("origin/{}", branch);
 
@@ -110,3 +331,299 @@ fn reset_local_repo_to_commit<P: AsRef<Path>>(
 
     Ok(repo)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::library::LibraryBackend;
+    use super::*;
+    use lazy_static::lazy_static;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    lazy_static! {
+        static ref REMOTES: Mutex<HashMap<String, PathBuf>> = Mutex::new(HashMap::new());
+    }
+
+    /// Serves remotes from local directories through libgit2.
+    struct LocalBackend;
+
+    impl LocalBackend {
+        fn path(&self, url: &str) -> String {
+            REMOTES.lock().unwrap()[url].to_str().unwrap().to_owned()
+        }
+    }
+
+    /// Makes `url` fetch from the repository at `dir`.
+    fn serve(url: &GitUrl, dir: &Path) {
+        REMOTES
+            .lock()
+            .unwrap()
+            .insert(url.remote_url().to_owned(), dir.to_owned());
+        backend::set_backend(Arc::new(LocalBackend));
+    }
+
+    impl GitBackend for LocalBackend {
+        fn clone_repo(
+            &self,
+            url: &str,
+            into: &Path,
+            branch: &str,
+            timeout: Option<Duration>,
+        ) -> Result<()> {
+            LibraryBackend.clone_repo(&self.path(url), into, branch, timeout)
+        }
+
+        fn fetch(
+            &self,
+            repo_path: &Path,
+            url: &str,
+            branch: &str,
+            timeout: Option<Duration>,
+        ) -> Result<()> {
+            LibraryBackend.fetch(repo_path, &self.path(url), branch, timeout)
+        }
+
+        fn default_branch(&self, url: &str, timeout: Option<Duration>) -> Result<Option<String>> {
+            LibraryBackend.default_branch(&self.path(url), timeout)
+        }
+
+        fn update_submodule(
+            &self,
+            repo_path: &Path,
+            path: &Path,
+            url: &str,
+            timeout: Option<Duration>,
+        ) -> Result<()> {
+            LibraryBackend.update_submodule(repo_path, path, url, timeout)
+        }
+    }
+
+    fn commit(repo: &Repository, file: &str) -> Oid {
+        let workdir = repo.workdir().unwrap();
+        fs::write(workdir.join(file), file).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(file)).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("protovend", "protovend@example.com").unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &signature, &signature, file, &tree, &parents)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_checks_out_from_substituted_backend() {
+        let remote_dir = tempfile::tempdir().unwrap();
+        let remote = Repository::init(remote_dir.path()).unwrap();
+        remote.set_head("refs/heads/main").unwrap();
+        let first = commit(&remote, "a.proto");
+
+        let name = remote_dir.path().file_name().unwrap().to_str().unwrap();
+        let url: GitUrl = format!("https://fake.test/user/{}.git", name.to_lowercase())
+            .parse()
+            .unwrap();
+        serve(&url, remote_dir.path());
+
+        assert_eq!("main", get_default_branch(&url).unwrap());
+        assert_eq!(first, get_latest_commit_sha(&url, "main").unwrap());
+
+        let second = commit(&remote, "b.proto");
+        assert_eq!(second, get_latest_commit_sha(&url, "main").unwrap());
+        let (first, second) = (first.to_string(), second.to_string());
+        assert!(is_descendant(&url, &second, &first).unwrap());
+        assert!(!is_descendant(&url, &first, &second).unwrap());
+
+        let error = get_repo(&url, "master", "HEAD").err().unwrap();
+        match error.downcast_ref::<GitError>() {
+            Some(GitError::BranchNotFound { default_branch, .. }) => {
+                assert_eq!(Some(String::from("main")), *default_branch)
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+
+        fs::remove_dir_all(get_destination_path(&url)).unwrap();
+    }
+
+    #[test]
+    fn test_checks_out_submodules_at_recorded_commits() {
+        let sub_dir = tempfile::tempdir().unwrap();
+        let sub = Repository::init(sub_dir.path()).unwrap();
+        let recorded = commit(&sub, "a.proto");
+
+        let parent_dir = tempfile::tempdir().unwrap();
+        let parent = Repository::init(parent_dir.path()).unwrap();
+        parent.set_head("refs/heads/main").unwrap();
+        commit(&parent, "README.md");
+        let status = std::process::Command::new("git")
+            .current_dir(parent_dir.path())
+            .args(["-c", "protocol.file.allow=always", "submodule", "add", "-q"])
+            .arg(sub_dir.path())
+            .arg("proto/sub")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let mut index = parent.index().unwrap();
+        index.read(true).unwrap();
+        let tree = parent.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("protovend", "protovend@example.com").unwrap();
+        let head = parent.head().unwrap().peel_to_commit().unwrap();
+        parent
+            .commit(Some("HEAD"), &signature, &signature, "sub", &tree, &[&head])
+            .unwrap();
+        // Later submodule commits aren't checked out until the superproject records them
+        commit(&sub, "b.proto");
+
+        let name = parent_dir.path().file_name().unwrap().to_str().unwrap();
+        let url: GitUrl = format!("https://fake.test/submodules/{}.git", name.to_lowercase())
+            .parse()
+            .unwrap();
+        serve(&url, parent_dir.path());
+
+        let revision = get_latest_commit_sha(&url, "main").unwrap().to_string();
+        let commits = get_submodule_commits(&url, &revision, "proto").unwrap();
+        assert_eq!(
+            Some(&recorded.to_string()),
+            commits.get("proto/sub"),
+            "{:?}",
+            commits
+        );
+        assert!(get_submodule_commits(&url, &revision, "other")
+            .unwrap()
+            .is_empty());
+
+        let repo = get_repo(&url, "main", &revision).unwrap();
+        assert!(
+            update_submodules(&repo, "proto", |url| Err(format_err!("refused {}", url))).is_err()
+        );
+        assert!(!repo.workdir().unwrap().join("proto/sub/a.proto").exists());
+        assert_eq!(
+            commits,
+            update_submodules(&repo, "proto", |_| Ok(())).unwrap()
+        );
+        let workdir = repo.workdir().unwrap();
+        assert!(workdir.join("proto/sub/a.proto").exists());
+        assert!(!workdir.join("proto/sub/b.proto").exists());
+
+        fs::remove_dir_all(get_destination_path(&url)).unwrap();
+    }
+
+    fn ssh_key(dir: &Path, name: &str) -> (PathBuf, TrustedKey) {
+        let key = dir.join(name);
+        let status = std::process::Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+            .arg(&key)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let public = fs::read_to_string(key.with_extension("pub")).unwrap();
+        (key, public.parse().unwrap())
+    }
+
+    fn git_commit(dir: &Path, file: &str, signing_key: Option<&Path>) -> String {
+        fs::write(dir.join(file), file).unwrap();
+        let git = |args: &[&str]| {
+            let output = std::process::Command::new("git")
+                .current_dir(dir)
+                .args([
+                    "-c",
+                    "user.name=protovend",
+                    "-c",
+                    "user.email=p@example.com",
+                ])
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{:?}", output);
+            String::from_utf8(output.stdout).unwrap()
+        };
+        git(&["add", file]);
+        match signing_key {
+            Some(key) => git(&[
+                "-c",
+                "gpg.format=ssh",
+                "-c",
+                &format!("user.signingkey={}", key.display()),
+                "commit",
+                "-q",
+                "-S",
+                "-m",
+                file,
+            ]),
+            None => git(&["commit", "-q", "-m", file]),
+        };
+        git(&["rev-parse", "HEAD"]).trim().to_owned()
+    }
+
+    #[test]
+    fn test_verifies_signatures_against_trusted_keys() {
+        let keys_dir = tempfile::tempdir().unwrap();
+        let (signing_key, trusted) = ssh_key(keys_dir.path(), "trusted");
+        let (_, untrusted) = ssh_key(keys_dir.path(), "untrusted");
+
+        let remote_dir = tempfile::tempdir().unwrap();
+        Repository::init(remote_dir.path())
+            .unwrap()
+            .set_head("refs/heads/main")
+            .unwrap();
+        let signed = git_commit(remote_dir.path(), "a.proto", Some(&signing_key));
+
+        let name = remote_dir.path().file_name().unwrap().to_str().unwrap();
+        let url: GitUrl = format!("https://fake.test/signed/{}.git", name.to_lowercase())
+            .parse()
+            .unwrap();
+        serve(&url, remote_dir.path());
+
+        assert_eq!(
+            signed,
+            get_latest_commit_sha(&url, "main").unwrap().to_string()
+        );
+        assert!(verify_signature(&url, "main", &signed, &[]).is_ok());
+        assert!(verify_signature(&url, "main", &signed, std::slice::from_ref(&trusted)).is_ok());
+        let error = verify_signature(&url, "main", &signed, &[untrusted])
+            .err()
+            .unwrap();
+        assert!(
+            error.to_string().contains("not in trusted_keys"),
+            "{}",
+            error
+        );
+
+        let unsigned = git_commit(remote_dir.path(), "b.proto", None);
+        assert_eq!(
+            unsigned,
+            get_latest_commit_sha(&url, "main").unwrap().to_string()
+        );
+        let error = verify_signature(&url, "main", &unsigned, &[trusted])
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("is not signed"), "{}", error);
+
+        fs::remove_dir_all(get_destination_path(&url)).unwrap();
+    }
+
+    #[test]
+    fn test_checks_out_tags() {
+        let remote_dir = tempfile::tempdir().unwrap();
+        let remote = Repository::init(remote_dir.path()).unwrap();
+        let tagged = commit(&remote, "a.proto");
+        let signature = git2::Signature::now("protovend", "protovend@example.com").unwrap();
+        let target = remote.find_object(tagged, None).unwrap();
+        remote
+            .tag("v1.0.0", &target, &signature, "v1.0.0", false)
+            .unwrap();
+        commit(&remote, "b.proto");
+
+        let name = remote_dir.path().file_name().unwrap().to_str().unwrap();
+        let url: GitUrl = format!("https://fake.test/tags/{}.git#v1.0.0", name.to_lowercase())
+            .parse()
+            .unwrap();
+        serve(&url, remote_dir.path());
+
+        assert_eq!(tagged, get_latest_commit_sha(&url, "v1.0.0").unwrap());
+        // Again, through the fetch of an existing clone
+        assert_eq!(tagged, get_latest_commit_sha(&url, "v1.0.0").unwrap());
+
+        fs::remove_dir_all(get_destination_path(&url)).unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+/*
+ * Copyright 2020 Skyscanner Limited.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use super::commands::CliBackend;
+use super::library::LibraryBackend;
+use super::GitError;
+use crate::settings::{self, Backend, NetworkSettings};
+use crate::Result;
+#[cfg(test)]
+use std::cell::RefCell;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[cfg(test)]
+thread_local! {
+    // Each test runs on its own thread, so a substitute backend only lasts for its test
+    static BACKEND_OVERRIDE: RefCell<Option<Arc<dyn GitBackend>>> = RefCell::new(None);
+}
+
+/// The network operations protovend needs from git. Failures should be reported as
+/// `GitError`s so they can be retried and explained consistently.
+pub trait GitBackend: Send + Sync {
+    /// Clones `branch` of `url` into the empty directory `into`, naming the remote `origin`.
+    fn clone_repo(
+        &self,
+        url: &str,
+        into: &Path,
+        branch: &str,
+        timeout: Option<Duration>,
+    ) -> Result<()>;
+
+    /// Brings `refs/remotes/origin/<branch>` of the clone at `repo_path` up to date with
+    /// `branch` of `url`.
+    fn fetch(
+        &self,
+        repo_path: &Path,
+        url: &str,
+        branch: &str,
+        timeout: Option<Duration>,
+    ) -> Result<()>;
+
+    /// The branch the remote's `HEAD` points at, if it advertises one.
+    fn default_branch(&self, url: &str, timeout: Option<Duration>) -> Result<Option<String>>;
+
+    /// Checks out the initialised submodule at `path` of the clone at `repo_path` at the
+    /// commit its superproject records, fetching it from `url`.
+    fn update_submodule(
+        &self,
+        repo_path: &Path,
+        path: &Path,
+        url: &str,
+        timeout: Option<Duration>,
+    ) -> Result<()>;
+}
+
+/// The backend chosen in settings, unless a test has substituted one with `set_backend`.
+pub fn current() -> Arc<dyn GitBackend> {
+    #[cfg(test)]
+    {
+        if let Some(backend) = BACKEND_OVERRIDE.with(|backend| backend.borrow().clone()) {
+            return backend;
+        }
+    }
+
+    match settings::current().git.backend {
+        Backend::Cli => Arc::new(CliBackend),
+        Backend::Library => Arc::new(LibraryBackend),
+    }
+}
+
+/// Substitutes `backend` for the rest of the calling test.
+#[cfg(test)]
+pub fn set_backend(backend: Arc<dyn GitBackend>) {
+    BACKEND_OVERRIDE.with(|current| *current.borrow_mut() = Some(backend));
+}
+
+/// Runs `attempt` until it succeeds, fails with a non-transient error, or the retries
+/// configured in `network` are used up.
+pub fn with_retries<T, F>(network: &NetworkSettings, mut attempt: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let attempts = network.retries + 1;
+    for number in 1.. {
+        let error = match attempt() {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+
+        let git_error = match error.downcast_ref::<GitError>() {
+            Some(git_error) if git_error.is_transient() && number < attempts => git_error,
+            _ => return Err(error),
+        };
+
+        let delay = network.backoff(number);
+        log::warn!(
+            "{} (attempt {}/{}), retrying in {:.1}s",
+            git_error.headline(),
+            number,
+            attempts,
+            delay.as_secs_f32()
+        );
+        thread::sleep(delay);
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn network(retries: u32) -> NetworkSettings {
+        NetworkSettings {
+            timeout: 1,
+            retries,
+            backoff_ms: 0,
+        }
+    }
+
+    fn unreachable() -> failure::Error {
+        GitError::from_stderr(
+            "fetch",
+            "https://github.com/user/project.git",
+            "master",
+            Some(128),
+            b"fatal: Could not resolve host: github.com",
+        )
+        .into()
+    }
+
+    #[test]
+    fn test_retries_transient_failures() {
+        let calls = Cell::new(0);
+        let result = with_retries(&network(2), || {
+            calls.set(calls.get() + 1);
+            if calls.get() < 3 {
+                Err(unreachable())
+            } else {
+                Ok(())
+            }
+        });
+
+        assert!(result.is_ok());
+        assert_eq!(3, calls.get());
+    }
+
+    #[test]
+    fn test_gives_up_after_retries() {
+        let calls = Cell::new(0);
+        let result: Result<()> = with_retries(&network(1), || {
+            calls.set(calls.get() + 1);
+            Err(unreachable())
+        });
+
+        assert!(result.is_err());
+        assert_eq!(2, calls.get());
+    }
+
+    #[test]
+    fn test_does_not_retry_permanent_failures() {
+        let calls = Cell::new(0);
+        let result: Result<()> = with_retries(&network(3), || {
+            calls.set(calls.get() + 1);
+            Err(failure::format_err!("Repository not found"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(1, calls.get());
+    }
+}
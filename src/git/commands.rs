@@ -14,48 +14,205 @@
  * limitations under the License.
 */
 
+use super::backend::GitBackend;
+use super::GitError;
+use crate::util;
 use crate::Result;
-use failure::format_err;
+use std::io::Read;
 use std::path::Path;
 use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub fn fetch<P: AsRef<Path>>(cwd: P, branch_name: &str, remote_name: &str) -> Result<()> {
-    let status = Command::new("git")
-        .current_dir(cwd)
-        .arg("fetch")
-        .arg(remote_name)
-        .arg(branch_name)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()?;
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
 
+/// Shells out to the `git` binary on the `PATH`, picking up the user's git config,
+/// credential helpers and SSH setup.
+pub struct CliBackend;
+
+impl GitBackend for CliBackend {
+    fn clone_repo(
+        &self,
+        url: &str,
+        into: &Path,
+        branch: &str,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let mut command = Command::new("git");
+        command
+            .current_dir(into)
+            .arg("clone")
+            .arg(url)
+            .arg("--branch")
+            .arg(branch)
+            .arg(".");
+        run("clone", url, branch, timeout, command).map(|_| ())
+    }
+
+    fn fetch(
+        &self,
+        repo_path: &Path,
+        url: &str,
+        branch: &str,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let mut command = Command::new("git");
+        command
+            .current_dir(repo_path)
+            .arg("fetch")
+            .arg("--no-tags")
+            .arg(url)
+            // Left unqualified so git resolves it as either a branch or a tag
+            .arg(format!("+{0}:refs/remotes/origin/{0}", branch));
+        run("fetch", url, branch, timeout, command).map(|_| ())
+    }
+
+    fn update_submodule(
+        &self,
+        repo_path: &Path,
+        path: &Path,
+        url: &str,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let mut command = Command::new("git");
+        command.current_dir(repo_path);
+        // The submodule's configured URL is kept free of credentials, so put them back
+        // for this command only
+        let configured = util::strip_credentials(url);
+        if configured != url {
+            command
+                .arg("-c")
+                .arg(format!("url.{}.insteadOf={}", url, configured));
+        }
+        command
+            .arg("submodule")
+            .arg("update")
+            .arg("--init")
+            .arg("--force")
+            .arg("--")
+            .arg(path);
+        let path = path.to_string_lossy();
+        run("submodule update", url, &path, timeout, command).map(|_| ())
+    }
+
+    fn default_branch(&self, url: &str, timeout: Option<Duration>) -> Result<Option<String>> {
+        let mut command = Command::new("git");
+        command
+            .arg("ls-remote")
+            .arg("--symref")
+            .arg(url)
+            .arg("HEAD");
+        let stdout = run("ls-remote", url, "HEAD", timeout, command)?;
+
+        Ok(parse_symref(&String::from_utf8_lossy(&stdout)))
+    }
+}
+
+fn parse_symref(ls_remote: &str) -> Option<String> {
+    ls_remote.lines().find_map(|line| {
+        let mut parts = line.split('\t');
+        match (parts.next(), parts.next()) {
+            (Some(target), Some("HEAD")) => target
+                .strip_prefix("ref: refs/heads/")
+                .map(|branch| branch.trim().to_owned()),
+            _ => None,
+        }
+    })
+}
+
+/// Runs `command`, killing it once `timeout` passes, and returns its stdout.
+fn run(
+    operation: &'static str,
+    url: &str,
+    branch: &str,
+    timeout: Option<Duration>,
+    mut command: Command,
+) -> Result<Vec<u8>> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Drain both pipes on other threads so a chatty git can't block on a full pipe
+    let stdout = read_in_background(child.stdout.take().unwrap());
+    let stderr = read_in_background(child.stderr.take().unwrap());
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        match timeout {
+            Some(timeout) if started.elapsed() >= timeout => {
+                child.kill()?;
+                child.wait()?;
+                // Helpers spawned by git may still hold the pipes open, so don't wait on them
+                let stderr = stderr.try_recv().unwrap_or_default();
+                return Err(GitError::timed_out(operation, url, timeout, &stderr).into());
+            }
+            _ => thread::sleep(POLL_INTERVAL),
+        }
+    };
+
+    let stderr = stderr.recv().unwrap_or_default();
     if status.success() {
-        Ok(())
+        Ok(stdout.recv().unwrap_or_default())
     } else {
-        Err(format_err!(
-            "Git fetch failed with code {:?}",
-            status.code()
-        ))
+        Err(GitError::from_stderr(operation, url, branch, status.code(), &stderr).into())
     }
 }
 
-pub fn clone<P: AsRef<Path>>(cwd: P, url: &str, branch: &str) -> Result<()> {
-    let output = Command::new("git")
-        .current_dir(cwd)
-        .arg("clone")
-        .arg(url)
-        .arg("--branch")
-        .arg(branch)
-        .arg(".")
-        .output()?;
-
-    if output.status.success() {
-        Ok(())
-    } else {
-        dbg!(&output);
-        Err(format_err!(
-            "Git clone failed with code {:?}",
-            output.status.code()
-        ))
+fn read_in_background<R: Read + Send + 'static>(mut pipe: R) -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut contents = Vec::new();
+        let _ = pipe.read_to_end(&mut contents);
+        let _ = sender.send(contents);
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_default_branch_from_symref() {
+        let ls_remote = "ref: refs/heads/main\tHEAD\n\
+                         9f2c6e0d3a8b7f41c5e2d0a1b3c4d5e6f7a8b9c0\tHEAD\n";
+
+        assert_eq!(Some(String::from("main")), parse_symref(ls_remote));
+        assert_eq!(
+            None,
+            parse_symref("9f2c6e0d3a8b7f41c5e2d0a1b3c4d5e6f7a8b9c0\tHEAD\n")
+        );
+    }
+
+    #[test]
+    fn test_kills_command_after_timeout() {
+        // Runs sleep_until_killed from this test binary, as sleep isn't available everywhere
+        let mut command = Command::new(std::env::current_exe().unwrap());
+        command.args([
+            "--ignored",
+            "--exact",
+            "git::commands::tests::sleep_until_killed",
+        ]);
+
+        let started = Instant::now();
+        let error = run("fetch", "", "master", Some(Duration::from_secs(1)), command).unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(matches!(
+            error.downcast_ref::<GitError>(),
+            Some(GitError::TimedOut { .. })
+        ));
+    }
+
+    #[test]
+    #[ignore]
+    fn sleep_until_killed() {
+        thread::sleep(Duration::from_secs(5));
     }
 }
@@ -0,0 +1,340 @@
+/*
+ * Copyright 2020 Skyscanner Limited.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use super::backend::GitBackend;
+use super::GitError;
+use crate::util;
+use crate::Result;
+use failure::format_err;
+use git2::build::CheckoutBuilder;
+use git2::{
+    AutotagOption, Config, Cred, CredentialType, Direction, FetchOptions, Remote, RemoteCallbacks,
+    Repository, SubmoduleUpdateOptions,
+};
+use std::cell::Cell;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MAX_CREDENTIAL_ATTEMPTS: usize = 3;
+
+/// Talks to remotes through libgit2, so no `git` binary is needed. Without the
+/// `libgit2-transport` feature only `git://` and local remotes are reachable.
+pub struct LibraryBackend;
+
+impl GitBackend for LibraryBackend {
+    fn clone_repo(
+        &self,
+        url: &str,
+        into: &Path,
+        branch: &str,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let (owned_url, into, branch) = (url.to_owned(), into.to_owned(), branch.to_owned());
+        bounded("clone", url, timeout, move |deadline| {
+            clone_repo(&owned_url, &into, &branch, deadline)
+        })
+    }
+
+    fn fetch(
+        &self,
+        repo_path: &Path,
+        url: &str,
+        branch: &str,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let (owned_url, repo_path, branch) =
+            (url.to_owned(), repo_path.to_owned(), branch.to_owned());
+        bounded("fetch", url, timeout, move |deadline| {
+            let repo = Repository::open(repo_path)?;
+            let mut remote = repo.remote_anonymous(&owned_url)?;
+            fetch_ref(&mut remote, &owned_url, &branch, deadline, "fetch")
+        })
+    }
+
+    fn update_submodule(
+        &self,
+        repo_path: &Path,
+        path: &Path,
+        url: &str,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let (owned_url, repo_path, path) = (url.to_owned(), repo_path.to_owned(), path.to_owned());
+        bounded("submodule update", url, timeout, move |deadline| {
+            update_submodule(&repo_path, &path, &owned_url, deadline)
+        })
+    }
+
+    fn default_branch(&self, url: &str, timeout: Option<Duration>) -> Result<Option<String>> {
+        let owned_url = url.to_owned();
+        bounded("ls-remote", url, timeout, move |deadline| {
+            default_branch(&owned_url, deadline)
+        })
+    }
+}
+
+/// Runs `f` on another thread, giving up on it once `timeout` passes. libgit2 calls
+/// nothing back while it connects, so the deadline can't be checked from its callbacks
+/// alone; they still abort the abandoned attempt at their next chance. Until then it may
+/// write to the repository, so giving up on it is not retried.
+fn bounded<T, F>(operation: &'static str, url: &str, timeout: Option<Duration>, f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&Deadline) -> Result<T> + Send + 'static,
+{
+    let deadline = Deadline::new(timeout);
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return f(&deadline),
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(f(&deadline));
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(GitError::abandoned(operation, url, timeout).into()),
+        Err(RecvTimeoutError::Disconnected) => Err(format_err!(
+            "libgit2 {} of {} stopped unexpectedly",
+            operation,
+            util::redact_credentials(url)
+        )),
+    }
+}
+
+fn clone_repo(url: &str, into: &Path, branch: &str, deadline: &Deadline) -> Result<()> {
+    let repo = Repository::init(into)?;
+    let mut remote = repo.remote("origin", url)?;
+    fetch_ref(&mut remote, url, branch, deadline, "clone")?;
+
+    // Like `git clone --branch`, leave HEAD detached at the branch or tag
+    let commit = repo
+        .find_reference(&format!("refs/remotes/origin/{}", branch))?
+        .peel_to_commit()?;
+    repo.set_head_detached(commit.id())?;
+    repo.checkout_head(Some(CheckoutBuilder::new().force()))?;
+    Ok(())
+}
+
+fn update_submodule(repo_path: &Path, path: &Path, url: &str, deadline: &Deadline) -> Result<()> {
+    let repo = Repository::open(repo_path)?;
+    let mut submodule = repo.find_submodule(&path.to_string_lossy())?;
+
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.remote_callbacks(callbacks(deadline));
+    let mut checkout = CheckoutBuilder::new();
+    checkout.force();
+    let mut options = SubmoduleUpdateOptions::new();
+    options.fetch(fetch_options).checkout(checkout);
+
+    // The configured URL is kept free of credentials, so use `url` for this update only and
+    // strip it again afterwards, from the submodule's origin as well
+    let key = format!("submodule.{}.url", submodule.name().unwrap_or_default());
+    let configured = util::strip_credentials(url);
+    repo.config()?.set_str(&key, url)?;
+    if let Ok(sub) = submodule.open() {
+        sub.remote_set_url("origin", url)?;
+    }
+    let result = submodule.update(true, Some(&mut options));
+    repo.config()?.set_str(&key, &configured)?;
+    if let Ok(sub) = submodule.open() {
+        sub.remote_set_url("origin", &configured)?;
+    }
+
+    result.map_err(|e| {
+        to_git_error(
+            "submodule update",
+            url,
+            &path.to_string_lossy(),
+            deadline,
+            &e,
+        )
+        .into()
+    })
+}
+
+fn default_branch(url: &str, deadline: &Deadline) -> Result<Option<String>> {
+    // libgit2 can only create remotes inside a repository, so keep a scratch one around
+    let scratch = Repository::init_bare(crate::REPOS_CACHE_DIRECTORY.join(".ls-remote"))?;
+    let mut remote = scratch.remote_anonymous(url)?;
+
+    with_heads(&mut remote, deadline, |heads| {
+        heads
+            .iter()
+            .find(|(name, _)| name == "HEAD")
+            .and_then(|(_, target)| target.as_ref())
+            .and_then(|target| target.strip_prefix("refs/heads/"))
+            .map(str::to_owned)
+    })
+    .map_err(|e| to_git_error("ls-remote", url, "HEAD", deadline, &e).into())
+}
+
+/// Fetches the branch or tag `name` of `remote` into `refs/remotes/origin/<name>`.
+fn fetch_ref(
+    remote: &mut Remote,
+    url: &str,
+    name: &str,
+    deadline: &Deadline,
+    operation: &'static str,
+) -> Result<()> {
+    // libgit2 quietly fetches nothing for a refspec that matches no ref
+    let candidates = [
+        format!("refs/heads/{}", name),
+        format!("refs/tags/{}", name),
+    ];
+    let advertised = with_heads(remote, deadline, |heads| {
+        candidates
+            .iter()
+            .find(|candidate| heads.iter().any(|(head, _)| head == *candidate))
+            .cloned()
+    })
+    .map_err(|e| to_git_error(operation, url, name, deadline, &e))?;
+    let source = advertised.ok_or_else(|| {
+        GitError::from_stderr(
+            operation,
+            url,
+            name,
+            None,
+            format!("couldn't find remote ref {}", name).as_bytes(),
+        )
+    })?;
+
+    let refspec = format!("+{}:refs/remotes/origin/{}", source, name);
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.remote_callbacks(callbacks(deadline));
+    // Auto-followed tags otherwise take the place of the one asked for
+    fetch_options.download_tags(AutotagOption::None);
+    remote
+        .fetch(&[refspec.as_str()], Some(&mut fetch_options), None)
+        .map_err(|e| to_git_error(operation, url, name, deadline, &e).into())
+}
+
+#[derive(Clone, Copy)]
+struct Deadline {
+    started: Instant,
+    timeout: Option<Duration>,
+}
+
+impl Deadline {
+    fn new(timeout: Option<Duration>) -> Self {
+        Deadline {
+            started: Instant::now(),
+            timeout,
+        }
+    }
+
+    fn passed(&self) -> bool {
+        self.timeout
+            .map_or(false, |timeout| self.started.elapsed() >= timeout)
+    }
+}
+
+/// Connects to `remote` and hands `f` the advertised refs with their symbolic targets.
+fn with_heads<T, F>(
+    remote: &mut Remote,
+    deadline: &Deadline,
+    f: F,
+) -> std::result::Result<T, git2::Error>
+where
+    F: FnOnce(&[(String, Option<String>)]) -> T,
+{
+    let connection = remote.connect_auth(Direction::Fetch, Some(callbacks(deadline)), None)?;
+    let heads: Vec<(String, Option<String>)> = connection
+        .list()?
+        .iter()
+        .map(|head| {
+            (
+                head.name().to_owned(),
+                head.symref_target().map(str::to_owned),
+            )
+        })
+        .collect();
+    Ok(f(&heads))
+}
+
+fn callbacks(deadline: &Deadline) -> RemoteCallbacks<'_> {
+    let mut callbacks = RemoteCallbacks::new();
+    let attempts = Cell::new(0);
+    callbacks.credentials(move |url, username, allowed| {
+        // libgit2 keeps asking for as long as we keep answering
+        attempts.set(attempts.get() + 1);
+        if attempts.get() > MAX_CREDENTIAL_ATTEMPTS || deadline.passed() {
+            return Err(git2::Error::from_str("authentication failed"));
+        }
+        credentials(url, username, allowed)
+    });
+    // Returning false aborts the transfer
+    callbacks.sideband_progress(move |_| !deadline.passed());
+    callbacks.transfer_progress(move |_| !deadline.passed());
+    callbacks
+}
+
+fn credentials(
+    url: &str,
+    username: Option<&str>,
+    allowed: CredentialType,
+) -> std::result::Result<Cred, git2::Error> {
+    if allowed.contains(CredentialType::SSH_KEY) {
+        Cred::ssh_key_from_agent(username.unwrap_or("git"))
+    } else if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+        Cred::credential_helper(&Config::open_default()?, url, username)
+    } else {
+        Cred::default()
+    }
+}
+
+fn to_git_error(
+    operation: &'static str,
+    url: &str,
+    branch: &str,
+    deadline: &Deadline,
+    error: &git2::Error,
+) -> GitError {
+    match deadline.timeout {
+        Some(timeout) if deadline.passed() => {
+            GitError::timed_out(operation, url, timeout, error.message().as_bytes())
+        }
+        _ => GitError::from_git2(operation, url, branch, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gives_up_on_operations_past_their_deadline() {
+        let started = Instant::now();
+        let error = bounded(
+            "fetch",
+            "https://github.com/user/project.git",
+            Some(Duration::from_millis(100)),
+            |_| {
+                thread::sleep(Duration::from_secs(5));
+                Ok(())
+            },
+        )
+        .unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        match error.downcast_ref::<GitError>() {
+            Some(error @ GitError::TimedOut { .. }) => assert!(!error.is_transient()),
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,311 @@
+/*
+ * Copyright 2020 Skyscanner Limited.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use crate::Result;
+use failure::{format_err, Error};
+use git2::{Oid, Repository};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::process::Command;
+use std::str::FromStr;
+
+/// Principal given to every trusted SSH key, so a match can be told apart from a
+/// signature that is merely well-formed.
+const SSH_PRINCIPAL: &str = "protovend-trusted";
+
+/// A key whose signatures are trusted, as listed under `trusted_keys` in `.protovend.yml`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrustedKey {
+    /// A full GPG fingerprint, upper case without spaces. Short key IDs are too easily
+    /// forged to be trusted.
+    Gpg(String),
+    /// An SSH public key as found in a `.pub` file, comment and all.
+    Ssh(String),
+}
+
+impl FromStr for TrustedKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        match fields.as_slice() {
+            [key_type, _, ..]
+                if key_type.starts_with("ssh-")
+                    || key_type.starts_with("sk-ssh-")
+                    || key_type.starts_with("sk-ecdsa-")
+                    || key_type.contains("-sha2-") =>
+            {
+                Ok(TrustedKey::Ssh(s.trim().to_owned()))
+            }
+            _ => {
+                let id: String = fields.concat().to_uppercase();
+                let id = id.strip_prefix("0X").unwrap_or(&id).to_owned();
+                if id.len() == 40 && id.chars().all(|c| c.is_ascii_hexdigit()) {
+                    Ok(TrustedKey::Gpg(id))
+                } else {
+                    Err(format_err!(
+                        "Invalid trusted key {}, expected a full GPG fingerprint or an SSH public key",
+                        s
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl Display for TrustedKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TrustedKey::Gpg(id) | TrustedKey::Ssh(id) => write!(f, "{}", id),
+        }
+    }
+}
+
+impl Serialize for TrustedKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for TrustedKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// What `git verify-commit --raw` or `git verify-tag --raw` had to say about a signature.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Unsigned,
+    Trusted(String),
+    Untrusted(String),
+    MissingKey(String),
+    Bad,
+    Unverifiable(String),
+}
+
+/// Checks that the commit or tag `id` in `repo` is signed by one of `keys`, returning the
+/// key that signed it.
+pub fn verify(repo: &Repository, id: Oid, is_tag: bool, keys: &[TrustedKey]) -> Result<String> {
+    // git only trusts SSH keys listed in an allowed signers file
+    let allowed_signers = repo.path().join("protovend_allowed_signers");
+    let contents: String = keys
+        .iter()
+        .filter_map(|key| match key {
+            TrustedKey::Ssh(key) => {
+                let key: Vec<&str> = key.split_whitespace().take(2).collect();
+                Some(format!("{} {}\n", SSH_PRINCIPAL, key.join(" ")))
+            }
+            TrustedKey::Gpg(_) => None,
+        })
+        .collect();
+    fs::write(&allowed_signers, contents)?;
+
+    let output = Command::new("git")
+        .current_dir(repo.path())
+        .arg("-c")
+        .arg(format!(
+            "gpg.ssh.allowedSignersFile={}",
+            allowed_signers.display()
+        ))
+        .arg(if is_tag {
+            "verify-tag"
+        } else {
+            "verify-commit"
+        })
+        .arg("--raw")
+        .arg(id.to_string())
+        .output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    log::debug!("Signature check of {}: {}", id, stderr.trim());
+
+    let what = if is_tag { "Tag" } else { "Commit" };
+    match parse(&stderr, output.status.success(), keys) {
+        Outcome::Trusted(key) => Ok(key),
+        Outcome::Unsigned => Err(format_err!("{} {} is not signed", what, id)),
+        Outcome::Untrusted(key) => Err(format_err!(
+            "{} {} is signed by {}, which is not in trusted_keys",
+            what,
+            id,
+            key
+        )),
+        Outcome::MissingKey(key) => Err(format_err!(
+            "{} {} is signed by GPG key {}, which is not in your keyring. Import it with gpg --recv-keys {}",
+            what,
+            id,
+            key,
+            key
+        )),
+        Outcome::Bad => Err(format_err!("{} {} has a bad signature", what, id)),
+        Outcome::Unverifiable(reason) => Err(format_err!(
+            "Could not check the signature of {} {}: {}",
+            what.to_lowercase(),
+            id,
+            reason
+        )),
+    }
+}
+
+fn parse(stderr: &str, success: bool, keys: &[TrustedKey]) -> Outcome {
+    let mut gpg_key = None;
+    for line in stderr.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["[GNUPG:]", "BADSIG", ..] => return Outcome::Bad,
+            ["[GNUPG:]", "NO_PUBKEY", key, ..] => return Outcome::MissingKey((*key).to_owned()),
+            // The signing key's fingerprint comes first and its primary key's last
+            ["[GNUPG:]", "VALIDSIG", signing, .., primary] if success => {
+                let trusted = keys.iter().any(|key| match key {
+                    TrustedKey::Gpg(fingerprint) => {
+                        signing == fingerprint || primary == fingerprint
+                    }
+                    TrustedKey::Ssh(_) => false,
+                });
+                if trusted {
+                    return Outcome::Trusted((*primary).to_owned());
+                }
+                gpg_key = Some((*primary).to_owned());
+            }
+            _ => {}
+        }
+
+        if let Some(rest) = line.strip_prefix("Good \"git\" signature ") {
+            let key = rest.rsplit(' ').next().unwrap_or_default().to_owned();
+            if success && rest.starts_with(&format!("for {} ", SSH_PRINCIPAL)) {
+                return Outcome::Trusted(key);
+            }
+            return Outcome::Untrusted(key);
+        }
+    }
+
+    match gpg_key {
+        Some(key) => Outcome::Untrusted(key),
+        None if stderr.trim().is_empty() => Outcome::Unsigned,
+        None => Outcome::Unverifiable(stderr.trim().to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GPG_FINGERPRINT: &str = "DD23838187B5DAAE6379C99188F034F0254424D9";
+
+    fn gpg_output() -> String {
+        format!(
+            "[GNUPG:] NEWSIG\
+             \n[GNUPG:] KEY_CONSIDERED {0} 0\
+             \n[GNUPG:] GOODSIG 88F034F0254424D9 Test <t@e.x>\
+             \n[GNUPG:] VALIDSIG {0} 2026-10-19 1792383325 0 4 0 22 8 00 {0}\
+             \n[GNUPG:] TRUST_ULTIMATE 0 pgp\n",
+            GPG_FINGERPRINT
+        )
+    }
+
+    #[test]
+    fn test_parses_trusted_keys() {
+        assert_eq!(
+            TrustedKey::Gpg(String::from(GPG_FINGERPRINT)),
+            "0xdd23838187b5daae6379c99188f034f0254424d9"
+                .parse()
+                .unwrap()
+        );
+        assert_eq!(
+            TrustedKey::Gpg(String::from(GPG_FINGERPRINT)),
+            "DD23 8381 87B5 DAAE 6379  C991 88F0 34F0 2544 24D9"
+                .parse()
+                .unwrap()
+        );
+        assert_eq!(
+            TrustedKey::Ssh(String::from(
+                "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIA ci@example.com"
+            )),
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIA ci@example.com"
+                .parse()
+                .unwrap()
+        );
+        assert_eq!(
+            TrustedKey::Ssh(String::from(
+                "sk-ssh-ed25519@openssh.com AAAAGnNrLXNzaC1lZDI1NTE5 ci@example.com"
+            )),
+            "sk-ssh-ed25519@openssh.com AAAAGnNrLXNzaC1lZDI1NTE5 ci@example.com"
+                .parse()
+                .unwrap()
+        );
+        assert_eq!(
+            TrustedKey::Ssh(String::from(
+                "sk-ecdsa-sha2-nistp256@openssh.com AAAAInNrLWVjZHNh"
+            )),
+            "sk-ecdsa-sha2-nistp256@openssh.com AAAAInNrLWVjZHNh"
+                .parse()
+                .unwrap()
+        );
+        assert!("not a key".parse::<TrustedKey>().is_err());
+        // Short key IDs are easily forged
+        assert!("88F034F0254424D9".parse::<TrustedKey>().is_err());
+    }
+
+    #[test]
+    fn test_trusts_allowlisted_gpg_keys() {
+        let fingerprint = vec![TrustedKey::Gpg(String::from(GPG_FINGERPRINT))];
+        // Shares the fingerprint's trailing key ID
+        let other = vec![TrustedKey::Gpg(String::from(
+            "00000000000000000000000088F034F0254424D9",
+        ))];
+
+        assert_eq!(
+            Outcome::Trusted(String::from(GPG_FINGERPRINT)),
+            parse(&gpg_output(), true, &fingerprint)
+        );
+        assert_eq!(
+            Outcome::Untrusted(String::from(GPG_FINGERPRINT)),
+            parse(&gpg_output(), true, &other)
+        );
+    }
+
+    #[test]
+    fn test_reports_missing_and_bad_gpg_signatures() {
+        let missing = "[GNUPG:] NEWSIG\
+                       \n[GNUPG:] ERRSIG 88F034F0254424D9 22 8 00 1792383325 9 -\
+                       \n[GNUPG:] NO_PUBKEY 88F034F0254424D9\n";
+        let bad = "[GNUPG:] NEWSIG\n[GNUPG:] BADSIG 88F034F0254424D9 Test <t@e.x>\n";
+
+        assert_eq!(
+            Outcome::MissingKey(String::from("88F034F0254424D9")),
+            parse(missing, false, &[])
+        );
+        assert_eq!(Outcome::Bad, parse(bad, false, &[]));
+        assert_eq!(Outcome::Unsigned, parse("", false, &[]));
+    }
+
+    #[test]
+    fn test_ssh_signatures_must_match_an_allowed_signer() {
+        let trusted = "Good \"git\" signature for protovend-trusted with ED25519 key SHA256:aV90\n";
+        let untrusted =
+            "Good \"git\" signature with ED25519 key SHA256:aV90\nNo principal matched.\n";
+
+        assert_eq!(
+            Outcome::Trusted(String::from("SHA256:aV90")),
+            parse(trusted, true, &[])
+        );
+        assert_eq!(
+            Outcome::Untrusted(String::from("SHA256:aV90")),
+            parse(untrusted, false, &[])
+        );
+    }
+}
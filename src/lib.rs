@@ -14,7 +14,7 @@
  * limitations under the License.
 */
 
-use failure::Error;
+use failure::{format_err, Error};
 use lazy_static::lazy_static;
 use semver::Version;
 use std::env;
@@ -27,8 +27,16 @@ mod date_compat;
 mod git;
 pub mod git_url;
 mod lock;
+mod proto;
+pub mod settings;
 mod util;
 
+pub use lock::graph::GraphFormat;
+
+#[cfg(test)]
+#[path = "../tests_utils/mod.rs"]
+mod tests_utils;
+
 lazy_static! {
     static ref CRATE_VERSION: Version = Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
     static ref REPOS_CACHE_DIRECTORY: PathBuf = env::temp_dir().join(".protovend/repos");
@@ -43,13 +51,41 @@ pub fn init() -> Result<()> {
 
 pub fn add(
     url: git_url::GitUrl,
-    branch: String,
+    branch: Option<String>,
     proto_dir: String,
     proto_path: String,
     filename_regex: String,
     resolve_dependency: bool,
+    submodules: bool,
 ) -> Result<()> {
     let mut config = config::get_config()?;
+    // Refuse before looking anything up on a host that isn't allowed; add_dependency
+    // checks again, and warns when the check is overridden
+    if !settings::current().allow_any_host {
+        config.check_host(&url)?;
+    }
+
+    if url.has_credentials() {
+        log::warn!(
+            "Credentials in {} are not saved to {}. Configure a git credential helper so later installs can authenticate",
+            url,
+            config::PROTOVEND_YAML
+        );
+    }
+
+    let branch = match (branch, url.reference()) {
+        (Some(branch), Some(reference)) if branch != reference => {
+            return Err(format_err!(
+                "{} names {} but --branch is {}, please give only one",
+                url,
+                reference,
+                branch
+            ))
+        }
+        (Some(branch), _) => branch,
+        (None, Some(reference)) => reference.to_owned(),
+        (None, None) => git::get_default_branch(&url)?,
+    };
 
     let fixed_path = if proto_path.is_empty() {
         Path::new(&url.sanitised_path())
@@ -67,29 +103,51 @@ pub fn add(
         fixed_path,
         filename_regex,
         resolve_dependency,
+        submodules,
     )
 }
 
 pub fn install() -> Result<()> {
     let config = config::get_config()?;
     let mut lock = lock::load_lock()?;
-    lock.update_imports(config)?;
-    lock.vendor().map(|_| log_blurb())
+    lock.update_imports(&config)?;
+    vendor(&mut lock, &config)
 }
 
 //FIXME consider doing some sort of matching here?
 pub fn update(url: Option<git_url::GitUrl>) -> Result<()> {
     let config = config::get_config()?;
     let mut lock = lock::load_lock()?;
+    lock.refresh_imports(&config, url)?;
+    vendor(&mut lock, &config)
+}
 
-    if let Some(repo) = url {
-        lock.clear_imports(repo);
-    } else {
-        lock.clear_all_imports();
-    }
+pub fn resolve_lock() -> Result<()> {
+    let config = config::get_config()?;
+    let mut lock = lock::merge::resolve_conflicts()?;
+    lock.update_imports(&config)?;
+    vendor(&mut lock, &config)
+}
+
+pub fn merge_lock(ours: &Path, theirs: &Path) -> Result<()> {
+    lock::merge::merge_files(ours, theirs)
+}
 
-    lock.update_imports(config)?;
-    lock.vendor().map(|_| log_blurb())
+pub fn graph(format: GraphFormat) -> Result<()> {
+    lock::graph::graph(&lock::load_lock()?, format)
+}
+
+pub fn licenses() -> Result<()> {
+    lock::licenses::licenses(&lock::load_lock()?)
+}
+
+pub fn why(file: &Path) -> Result<()> {
+    lock::why::why(&lock::load_lock()?, file)
+}
+
+pub fn migrate() -> Result<()> {
+    config::migrate()?;
+    lock::migrate()
 }
 
 pub fn cleanup() -> Result<()> {
@@ -97,6 +155,10 @@ pub fn cleanup() -> Result<()> {
     Ok(())
 }
 
+fn vendor(lock: &mut lock::ProtovendLock, config: &config::ProtovendConfig) -> Result<()> {
+    lock.vendor(config).map(|_| log_blurb())
+}
+
 fn log_blurb() {
     log::info!("Next Steps:
 Check the following protovend generated files and vendored proto directory (containing .proto files) into source control
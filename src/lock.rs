@@ -14,28 +14,39 @@
  * limitations under the License.
 */
 
-use crate::config::Dependency;
+use crate::check;
 use crate::config::ProtovendConfig;
-use crate::git;
+use crate::config::{Dependency, OptionValue};
+use crate::git::{self, TrustedKey};
 use crate::git_url::GitUrl;
 use crate::util;
 use crate::{date_compat, Result};
-use chrono::{Local, NaiveDateTime};
+use chrono::{DateTime, TimeZone, Utc};
 use failure::format_err;
 use semver::Version;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
 use std::path::Path;
 
+pub mod graph;
+pub mod licenses;
+pub mod merge;
 pub mod vendor;
-
-#[cfg(test)]
-#[path = "../tests_utils/mod.rs"]
-mod tests_utils;
+pub mod why;
 
 pub const PROTOVEND_LOCK: &str = ".protovend.lock";
 
+/// Version of the `.protovend.lock` format this protovend writes. Like `CONFIG_VERSION`,
+/// it is left alone when optional fields are added.
+const LOCK_VERSION: u32 = 2;
+
+/// Reproducible builds convention for the time to stamp output with instead of now.
+const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
 struct Import {
     branch: String,
     commit: String,
@@ -44,103 +55,224 @@ struct Import {
     proto_paths: Vec<String>,
     filename_regex: String,
     resolve_dependency: bool,
+    #[serde(default, skip_serializing_if = "util::is_false")]
+    submodules: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    submodule_commits: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    packages: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    roots: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    options: BTreeMap<String, Option<OptionValue>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    relocate: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    license_file: Option<String>,
+    /// The license vendored with this import when it was last installed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    license: Option<License>,
+    /// Where the README of this import was vendored, next to its license, when it was last
+    /// installed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    readme: Option<String>,
+    /// What was vendored for this import when it was last installed, sorted by path.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    files: Vec<VendoredFile>,
+}
+
+/// A file vendored for an import, relative to the vendored proto directory.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct VendoredFile {
+    path: String,
+    /// The vendored file importing this one, when it doesn't match a proto_path itself
+    /// and was pulled in by resolve_dependency.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    imported_by: Option<String>,
 }
 
+/// The license file of an import, relative to the root of its repository.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct License {
+    path: String,
+    /// Where the license was vendored, relative to the vendored proto directory.
+    vendored: String,
+    /// The SPDX identifier of the license, when it could be recognised.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    spdx: Option<String>,
+}
+
+/// An import as locked before the lock file recorded a version, including by
+/// releases that predate `filename_regex` and `resolve_dependency`.
 #[derive(Deserialize)]
-struct LegacyImport {
+#[serde(deny_unknown_fields)]
+struct ImportV1 {
     branch: String,
     commit: String,
     url: GitUrl,
     proto_dir: String,
     proto_paths: Vec<String>,
+    filename_regex: Option<String>,
+    #[serde(default)]
+    resolve_dependency: bool,
+    #[serde(default)]
+    submodules: bool,
+    #[serde(default)]
+    submodule_commits: BTreeMap<String, String>,
 }
 
-impl From<LegacyImport> for Import {
-    fn from(import: LegacyImport) -> Self {
+impl From<ImportV1> for Import {
+    fn from(import: ImportV1) -> Self {
         Import {
-            url: import.url.clone(),
+            url: import.url,
             branch: import.branch,
             commit: import.commit,
             proto_dir: import.proto_dir,
             proto_paths: import.proto_paths,
-            filename_regex: String::from(".*"),
-            resolve_dependency: false,
+            filename_regex: import.filename_regex.unwrap_or_else(|| String::from(".*")),
+            resolve_dependency: import.resolve_dependency,
+            submodules: import.submodules,
+            submodule_commits: import.submodule_commits,
+            packages: Vec::new(),
+            roots: Vec::new(),
+            options: BTreeMap::new(),
+            relocate: BTreeMap::new(),
+            license_file: None,
+            license: None,
+            readme: None,
+            files: Vec::new(),
         }
     }
 }
 
 impl PartialEq<Dependency> for Import {
     fn eq(&self, other: &Dependency) -> bool {
-        self.url == other.url && self.branch == other.branch
+        self.url == other.url
+            && self.branch == other.branch
+            && self.submodules == other.submodules
+            && self.packages == other.packages
+            && self.roots == other.roots
+            && self.options == other.options
+            && self.relocate == other.relocate
+            && self.license_file == other.license_file
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct ProtovendLock {
+    version: u32,
     imports: Vec<Import>,
     min_protovend_version: Version,
     #[serde(with = "date_compat")]
-    updated: NaiveDateTime,
+    updated: DateTime<Utc>,
 }
 
 #[derive(Deserialize)]
-pub struct LegacyProtovendLock {
-    imports: Vec<LegacyImport>,
+#[serde(deny_unknown_fields)]
+struct LockV1 {
+    imports: Vec<ImportV1>,
     min_protovend_version: Version,
     #[serde(with = "date_compat")]
-    updated: NaiveDateTime,
+    updated: DateTime<Utc>,
 }
 
-impl From<LegacyProtovendLock> for ProtovendLock {
-    fn from(legacy_config: LegacyProtovendLock) -> Self {
+impl From<LockV1> for ProtovendLock {
+    fn from(lock: LockV1) -> Self {
         ProtovendLock {
-            min_protovend_version: legacy_config.min_protovend_version,
-            imports: legacy_config
-                .imports
-                .into_iter()
-                .map(|d| d.into())
-                .collect(),
-            updated: legacy_config.updated,
+            version: LOCK_VERSION,
+            min_protovend_version: lock.min_protovend_version,
+            imports: lock.imports.into_iter().map(|i| i.into()).collect(),
+            updated: lock.updated,
         }
     }
 }
 
-#[derive(Deserialize)]
-#[serde(untagged)]
-enum Lock {
-    Lock(ProtovendLock),
-    Legacy(LegacyProtovendLock),
-}
-
 impl ProtovendLock {
-    pub fn vendor(&self) -> Result<()> {
+    /// Vendors every import, rewriting the lock file if what was vendored has changed and
+    /// passes the import checks.
+    pub fn vendor(&mut self, config: &ProtovendConfig) -> Result<()> {
         vendor::prepare_output_directory()?;
 
-        for import in self.imports.iter() {
-            vendor::vendor_import(import)?;
+        let mut changed = false;
+        for import in self.imports.iter_mut() {
+            let (files, license, readme) = vendor::vendor_import(import, config)?;
+            if files != import.files || license != import.license || readme != import.readme {
+                import.files = files;
+                import.license = license;
+                import.readme = readme;
+                changed = true;
+            }
         }
 
-        Ok(())
+        check::run_import_checks(
+            Path::new(vendor::PROTOS_OUTPUT_DIRECTORY),
+            &config.include_roots,
+            config.strict_imports,
+        )?;
+
+        if changed {
+            self.write()
+        } else {
+            Ok(())
+        }
     }
 
     fn write(&mut self) -> Result<()> {
-        let f = File::create(Path::new(PROTOVEND_LOCK))?;
+        self.updated = timestamp()?;
+        self.save(Path::new(PROTOVEND_LOCK))
+    }
+
+    fn save(&mut self, lock_file: &Path) -> Result<()> {
+        let f = File::create(lock_file)?;
         self.imports.sort_by(|a, b| a.url.cmp(&b.url));
-        self.updated = Local::now().naive_local();
         Ok(serde_yaml::to_writer(f, &self)?)
     }
 
-    fn process_new_imports(&self, deps: Vec<Dependency>) -> Result<Vec<Import>> {
-        let (mut entries, added_entries) = diff_lock(deps, self.imports.clone());
-
-        let new_entries: Result<Vec<Import>> = added_entries.into_iter().map(to_import).collect();
+    fn process_new_imports(
+        deps: Vec<Dependency>,
+        imports: Vec<Import>,
+        trusted_keys: &[TrustedKey],
+    ) -> Result<Vec<Import>> {
+        let (mut entries, added_entries) = diff_lock(deps, imports);
+
+        let new_entries: Result<Vec<Import>> = added_entries
+            .into_iter()
+            .map(|dep| to_import(dep, trusted_keys))
+            .collect();
         entries.append(&mut new_entries?);
 
         Ok(entries)
     }
 
-    pub fn update_imports(&mut self, config: ProtovendConfig) -> Result<()> {
-        let new_imports = self.process_new_imports(config.vendor)?;
+    /// Locks dependencies that aren't locked yet, keeping the commits of those that are.
+    pub fn update_imports(&mut self, config: &ProtovendConfig) -> Result<()> {
+        let imports = self.imports.clone();
+        self.lock_imports(config, imports)
+    }
+
+    /// Locks `repo`, or every dependency when there is none, at its latest commit.
+    pub fn refresh_imports(
+        &mut self,
+        config: &ProtovendConfig,
+        repo: Option<GitUrl>,
+    ) -> Result<()> {
+        let mut imports = self.imports.clone();
+        match repo {
+            Some(repo) => imports.retain(|import| import.url != repo),
+            None => imports.clear(),
+        }
+        self.lock_imports(config, imports)
+    }
+
+    /// Rewrites the lock file only if the imports resolved from `config` differ from
+    /// those it holds, so that it doesn't change when nothing it locks has.
+    fn lock_imports(&mut self, config: &ProtovendConfig, imports: Vec<Import>) -> Result<()> {
+        let mut new_imports =
+            Self::process_new_imports(config.vendor.clone(), imports, &config.trusted_keys)?;
+        new_imports.sort_by(|a, b| a.url.cmp(&b.url));
         if new_imports != self.imports {
             self.imports = new_imports;
             self.write()
@@ -148,29 +280,42 @@ impl ProtovendLock {
             Ok(())
         }
     }
-
-    pub fn clear_all_imports(&mut self) {
-        self.imports.clear()
-    }
-
-    pub fn clear_imports(&mut self, repo: GitUrl) {
-        self.imports.retain(|import| import.url != repo)
-    }
 }
 
 pub fn load_lock() -> Result<ProtovendLock> {
     load_lockfile(Path::new(PROTOVEND_LOCK))
 }
 
+/// Rewrites `.protovend.lock` in the current format if it was written in an older one,
+/// leaving what it locks untouched.
+pub fn migrate() -> Result<()> {
+    let lock_file = Path::new(PROTOVEND_LOCK);
+    if !lock_file.exists() {
+        return Ok(());
+    }
+
+    let mut lock = load_lockfile(lock_file)?;
+    let contents = fs::read_to_string(lock_file)?;
+    let version = util::file_version(lock_file, &contents, LOCK_VERSION)?;
+    if version == LOCK_VERSION {
+        log::info!("{} is already version {}", PROTOVEND_LOCK, version);
+        Ok(())
+    } else {
+        lock.save(lock_file).map(|_| {
+            log::info!(
+                "Migrated {} from version {} to {}",
+                PROTOVEND_LOCK,
+                version,
+                LOCK_VERSION
+            )
+        })
+    }
+}
+
 fn load_lockfile(lock_file: &Path) -> Result<ProtovendLock> {
     if lock_file.exists() {
-        let f = File::open(lock_file)?;
-        let lock: Lock = serde_yaml::from_reader(f)?;
-
-        let lock: ProtovendLock = match lock {
-            Lock::Lock(p) => p,
-            Lock::Legacy(l) => l.into(),
-        };
+        let mut lock = parse_lockfile(lock_file)?;
+        lock.imports.sort_by(|a, b| a.url.cmp(&b.url));
 
         if util::is_valid_version(&lock.min_protovend_version) {
             Ok(lock)
@@ -179,22 +324,77 @@ fn load_lockfile(lock_file: &Path) -> Result<ProtovendLock> {
         }
     } else {
         Ok(ProtovendLock {
+            version: LOCK_VERSION,
             imports: Vec::new(),
             min_protovend_version: crate::CRATE_VERSION.clone(),
-            updated: Local::now().naive_local(),
+            updated: timestamp()?,
         })
     }
 }
 
-fn to_import(dep: Dependency) -> Result<Import> {
+/// The time to stamp the lock file with, taken from `SOURCE_DATE_EPOCH` when set.
+fn timestamp() -> Result<DateTime<Utc>> {
+    match env::var(SOURCE_DATE_EPOCH) {
+        Ok(epoch) => epoch
+            .trim()
+            .parse()
+            .ok()
+            .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single())
+            .ok_or_else(|| {
+                format_err!(
+                    "{} must be a number of seconds within the range of dates, not {}",
+                    SOURCE_DATE_EPOCH,
+                    epoch
+                )
+            }),
+        Err(_) => Ok(Utc.timestamp(Utc::now().timestamp(), 0)),
+    }
+}
+
+fn parse_lockfile(lock_file: &Path) -> Result<ProtovendLock> {
+    parse_lock(lock_file, &fs::read_to_string(lock_file)?)
+}
+
+/// Reads `contents` of `lock_file` in whichever version it was written, migrating each
+/// older version to the next in turn.
+fn parse_lock(lock_file: &Path, contents: &str) -> Result<ProtovendLock> {
+    let invalid = |e| format_err!("Invalid {}: {}", lock_file.display(), e);
+
+    match util::file_version(lock_file, contents, LOCK_VERSION)? {
+        1 => serde_yaml::from_str::<LockV1>(contents)
+            .map(ProtovendLock::from)
+            .map_err(invalid),
+        _ => serde_yaml::from_str(contents).map_err(invalid),
+    }
+}
+
+fn to_import(dep: Dependency, trusted_keys: &[TrustedKey]) -> Result<Import> {
+    let commit = git::get_latest_commit_sha(&dep.url, &dep.branch)?.to_string();
+    git::verify_signature(&dep.url, &dep.branch, &commit, trusted_keys)?;
+    let submodule_commits = if dep.submodules {
+        git::get_submodule_commits(&dep.url, &commit, &dep.proto_dir)?
+    } else {
+        BTreeMap::new()
+    };
+
     Ok(Import {
-        commit: git::get_latest_commit_sha(&dep.url, &dep.branch)?.to_string(),
+        commit,
         branch: dep.branch,
         url: dep.url,
         proto_dir: dep.proto_dir,
         proto_paths: dep.proto_paths,
         filename_regex: dep.filename_regex,
         resolve_dependency: dep.resolve_dependency,
+        submodules: dep.submodules,
+        submodule_commits,
+        packages: dep.packages,
+        roots: dep.roots,
+        options: dep.options,
+        relocate: dep.relocate,
+        license_file: dep.license_file,
+        license: None,
+        readme: None,
+        files: Vec::new(),
     })
 }
 
@@ -222,9 +422,10 @@ pub fn init() -> Result<()> {
         Ok(())
     } else {
         let mut lock = ProtovendLock {
+            version: LOCK_VERSION,
             imports: Vec::new(),
             min_protovend_version: crate::CRATE_VERSION.clone(),
-            updated: Local::now().naive_local(),
+            updated: timestamp()?,
         };
         lock.write()
             .map(|_| log::info!("Created {}", PROTOVEND_LOCK))
@@ -234,6 +435,7 @@ pub fn init() -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tests_utils;
 
     #[test]
     fn test_correctly_parses_lock() {
@@ -253,6 +455,7 @@ mod tests {
         let lock_path = tests_utils::fs::write_contents_to_temp_file(lock_contents, "lock");
 
         let expected_lock = ProtovendLock {
+            version: LOCK_VERSION,
             imports: vec![Import {
                 branch: String::from("master"),
                 commit: String::from("a9fef901ae63f689a4180bf8255d16a45baf04a1"),
@@ -263,15 +466,106 @@ mod tests {
                 proto_paths: vec![String::from("path/to")],
                 filename_regex: String::from(".*"),
                 resolve_dependency: true,
+                submodules: false,
+                submodule_commits: BTreeMap::new(),
+                packages: Vec::new(),
+                roots: Vec::new(),
+                options: BTreeMap::new(),
+                relocate: BTreeMap::new(),
+                license_file: None,
+                license: None,
+                readme: None,
+                files: Vec::new(),
             }],
             min_protovend_version: "0.1.8".parse::<Version>().unwrap(),
-            updated: "2019-11-20T15:02:12.330896"
-                .parse::<NaiveDateTime>()
-                .unwrap(),
+            updated: Utc.ymd(2019, 11, 20).and_hms_micro(15, 2, 12, 330896),
         };
 
         let actual_lock = load_lockfile(&lock_path).unwrap();
 
         assert_eq!(expected_lock, actual_lock);
     }
+
+    #[test]
+    fn test_migrates_legacy_lock() {
+        let lock_contents = "--- \
+             \nimports: \
+             \n  - branch: master \
+             \n    commit: a9fef901ae63f689a4180bf8255d16a45baf04a1 \
+             \n    url: https://github.com/user/project.git \
+             \n    proto_dir: proto \
+             \n    proto_paths: \
+             \n      - path/to \
+             \nmin_protovend_version: 0.1.8 \
+             \nupdated: \"2019-11-20 15:02:12.330896\"";
+
+        let lock_path = tests_utils::fs::write_contents_to_temp_file(lock_contents, "legacy_lock");
+
+        let actual_lock = load_lockfile(&lock_path).unwrap();
+
+        assert_eq!(LOCK_VERSION, actual_lock.version);
+        assert_eq!(".*", actual_lock.imports[0].filename_regex);
+        assert!(!actual_lock.imports[0].resolve_dependency);
+    }
+
+    #[test]
+    fn test_refuses_unknown_fields_in_legacy_lock() {
+        let lock_contents = "--- \
+             \nimports: \
+             \n  - branch: master \
+             \n    commit: a9fef901ae63f689a4180bf8255d16a45baf04a1 \
+             \n    url: https://github.com/user/project.git \
+             \n    proto_dir: proto \
+             \n    proto_paths: \
+             \n      - path/to \
+             \n    submodule: true \
+             \nmin_protovend_version: 0.1.8 \
+             \nupdated: \"2019-11-20 15:02:12.330896\"";
+
+        let lock_path =
+            tests_utils::fs::write_contents_to_temp_file(lock_contents, "legacy_typo_lock");
+
+        let error = load_lockfile(&lock_path).err().unwrap().to_string();
+
+        assert!(error.contains("unknown field `submodule`"), "{}", error);
+    }
+
+    #[test]
+    fn test_invalid_lock_names_line_and_field() {
+        let lock_contents = "--- \
+             \nversion: 2 \
+             \nimports: \
+             \n  - branch: master \
+             \n    comit: a9fef901ae63f689a4180bf8255d16a45baf04a1 \
+             \n    url: https://github.com/user/project.git \
+             \n    proto_dir: proto \
+             \n    proto_paths: \
+             \n      - path/to \
+             \n    filename_regex: \".*\"\
+             \n    resolve_dependency: false \
+             \nmin_protovend_version: 0.1.8 \
+             \nupdated: \"2019-11-20 15:02:12.330896\"";
+
+        let lock_path = tests_utils::fs::write_contents_to_temp_file(lock_contents, "invalid_lock");
+
+        let error = load_lockfile(&lock_path).err().unwrap().to_string();
+
+        assert!(error.contains("unknown field `comit`"), "{}", error);
+        assert!(error.contains("line 5"), "{}", error);
+    }
+
+    #[test]
+    fn test_writes_updated_in_utc() {
+        let lock = ProtovendLock {
+            version: LOCK_VERSION,
+            imports: Vec::new(),
+            min_protovend_version: "0.1.8".parse::<Version>().unwrap(),
+            updated: Utc.ymd(2020, 1, 1).and_hms(16, 1, 24),
+        };
+
+        let contents = serde_yaml::to_string(&lock).unwrap();
+
+        assert!(contents.contains("updated: \"2020-01-01T16:01:24Z\""));
+        assert_eq!(lock, serde_yaml::from_str(&contents).unwrap());
+    }
 }
@@ -0,0 +1,225 @@
+/*
+ * Copyright 2020 Skyscanner Limited.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use super::vendor::{self, PROTOS_OUTPUT_DIRECTORY};
+use super::ProtovendLock;
+use crate::Result;
+use failure::{format_err, Error};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How `protovend graph` writes the import graph.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraphFormat {
+    /// Graphviz DOT, with a cluster per dependency.
+    Dot,
+    /// JSON listing the files of each dependency, then every import.
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(GraphFormat::Dot),
+            "json" => Ok(GraphFormat::Json),
+            _ => Err(format_err!(
+                "Unknown graph format {}, expected dot or json",
+                s
+            )),
+        }
+    }
+}
+
+/// Vendored files grouped by the dependency they came from, and the imports between
+/// them. Imports of files protovend didn't vendor are kept, leading outside any group.
+#[derive(Debug, PartialEq, Serialize)]
+struct Graph {
+    dependencies: Vec<DependencyFiles>,
+    imports: BTreeSet<Edge>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct DependencyFiles {
+    url: String,
+    commit: String,
+    files: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+struct Edge {
+    from: String,
+    to: String,
+}
+
+/// Writes the import graph of every file vendored under `lock` to stdout.
+pub fn graph(lock: &ProtovendLock, format: GraphFormat) -> Result<()> {
+    let graph = build(lock, Path::new(PROTOS_OUTPUT_DIRECTORY))?;
+    print!("{}", render(&graph, format)?);
+    Ok(())
+}
+
+/// Reads the imports of each vendored file listed in `lock` from under `root`.
+fn build(lock: &ProtovendLock, root: &Path) -> Result<Graph> {
+    let mut dependencies = Vec::new();
+    let mut imports = BTreeSet::new();
+
+    for import in &lock.imports {
+        for file in &import.files {
+            let contents = fs::read_to_string(root.join(&file.path)).map_err(|e| {
+                format_err!(
+                    "Cannot read vendored {}: {}. Run protovend install first",
+                    file.path,
+                    e
+                )
+            })?;
+            for imported in vendor::proto_imports(vendor::upstream_contents(&contents)) {
+                imports.insert(Edge {
+                    from: file.path.clone(),
+                    to: imported.to_owned(),
+                });
+            }
+        }
+        dependencies.push(DependencyFiles {
+            url: import.url.to_string(),
+            commit: import.commit.clone(),
+            files: import.files.iter().map(|f| f.path.clone()).collect(),
+        });
+    }
+
+    Ok(Graph {
+        dependencies,
+        imports,
+    })
+}
+
+fn render(graph: &Graph, format: GraphFormat) -> Result<String> {
+    match format {
+        GraphFormat::Dot => Ok(render_dot(graph)?),
+        GraphFormat::Json => Ok(serde_json::to_string_pretty(graph)? + "\n"),
+    }
+}
+
+fn render_dot(graph: &Graph) -> std::result::Result<String, std::fmt::Error> {
+    let mut dot = String::from("digraph protovend {\n");
+    for (i, dependency) in graph.dependencies.iter().enumerate() {
+        writeln!(dot, "  subgraph cluster_{} {{", i)?;
+        writeln!(dot, "    label = {};", quote(&dependency.url))?;
+        for file in &dependency.files {
+            writeln!(dot, "    {};", quote(file))?;
+        }
+        writeln!(dot, "  }}")?;
+    }
+    for edge in &graph.imports {
+        writeln!(dot, "  {} -> {};", quote(&edge.from), quote(&edge.to))?;
+    }
+    dot.push_str("}\n");
+    Ok(dot)
+}
+
+fn quote(id: &str) -> String {
+    format!("\"{}\"", id.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lock::PROTOVEND_LOCK;
+
+    const LOCK: &str = "---
+version: 2
+imports:
+  - branch: main
+    commit: 1111111111111111111111111111111111111111
+    url: \"https://github.com/acme/api.git\"
+    proto_dir: proto
+    proto_paths:
+      - acme/api
+    filename_regex: \".*\"
+    resolve_dependency: true
+    files:
+      - path: acme/api/service.proto
+      - path: acme/common/types.proto
+        imported_by: acme/api/service.proto
+min_protovend_version: 4.2.0
+updated: \"2020-01-01T16:01:24Z\"
+";
+
+    fn graph() -> Graph {
+        let lock = super::super::parse_lock(Path::new(PROTOVEND_LOCK), LOCK).unwrap();
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("acme/api")).unwrap();
+        fs::create_dir_all(root.path().join("acme/common")).unwrap();
+        fs::write(
+            root.path().join("acme/api/service.proto"),
+            "import \"acme/common/types.proto\";\nimport \"google/protobuf/timestamp.proto\";\n",
+        )
+        .unwrap();
+        fs::write(root.path().join("acme/common/types.proto"), "").unwrap();
+
+        build(&lock, root.path()).unwrap()
+    }
+
+    #[test]
+    fn test_renders_dot() {
+        assert_eq!(
+            "digraph protovend {\
+             \n  subgraph cluster_0 {\
+             \n    label = \"https://github.com/acme/api.git\";\
+             \n    \"acme/api/service.proto\";\
+             \n    \"acme/common/types.proto\";\
+             \n  }\
+             \n  \"acme/api/service.proto\" -> \"acme/common/types.proto\";\
+             \n  \"acme/api/service.proto\" -> \"google/protobuf/timestamp.proto\";\
+             \n}\n",
+            render(&graph(), GraphFormat::Dot).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_renders_json() {
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&graph(), GraphFormat::Json).unwrap()).unwrap();
+
+        assert_eq!(
+            "https://github.com/acme/api.git",
+            json["dependencies"][0]["url"]
+        );
+        assert_eq!(
+            2,
+            json["dependencies"][0]["files"].as_array().unwrap().len()
+        );
+        assert_eq!(
+            serde_json::json!({"from": "acme/api/service.proto", "to": "acme/common/types.proto"}),
+            json["imports"][0]
+        );
+    }
+
+    #[test]
+    fn test_needs_vendored_files() {
+        let lock = super::super::parse_lock(Path::new(PROTOVEND_LOCK), LOCK).unwrap();
+        let root = tempfile::tempdir().unwrap();
+
+        let error = build(&lock, root.path()).err().unwrap();
+
+        assert!(error.to_string().contains("protovend install"), "{}", error);
+    }
+}
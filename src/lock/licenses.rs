@@ -0,0 +1,199 @@
+/*
+ * Copyright 2020 Skyscanner Limited.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use super::{ProtovendLock, PROTOVEND_LOCK};
+use crate::Result;
+use std::collections::BTreeMap;
+
+/// Phrases that together identify a license text, by its SPDX identifier. The first
+/// license whose phrases all appear wins, so more specific ones come first.
+const LICENSE_PHRASES: &[(&str, &[&str])] = &[
+    ("Apache-2.0", &["Apache License", "Version 2.0"]),
+    ("MPL-2.0", &["Mozilla Public License", "2.0"]),
+    (
+        "BSD-3-Clause",
+        &[
+            "Redistribution and use in source and binary forms",
+            "Neither the name",
+        ],
+    ),
+    (
+        "BSD-2-Clause",
+        &["Redistribution and use in source and binary forms"],
+    ),
+    ("MIT", &["Permission is hereby granted, free of charge"]),
+    (
+        "ISC",
+        &["Permission to use, copy, modify, and/or distribute this software for any purpose"],
+    ),
+    (
+        "Unlicense",
+        &["This is free and unencumbered software released into the public domain"],
+    ),
+    ("CC0-1.0", &["CC0 1.0 Universal"]),
+];
+
+/// Logs the license of each dependency in `lock`, followed by how many use each one.
+pub fn licenses(lock: &ProtovendLock) -> Result<()> {
+    for line in summarise(lock) {
+        log::info!("{}", line);
+    }
+    Ok(())
+}
+
+fn summarise(lock: &ProtovendLock) -> Vec<String> {
+    if lock.imports.is_empty() {
+        return vec![format!("There are no dependencies in {}", PROTOVEND_LOCK)];
+    }
+
+    let mut lines = Vec::new();
+    let mut counts = BTreeMap::new();
+    for import in &lock.imports {
+        let (line, spdx) = match &import.license {
+            Some(license) => match &license.spdx {
+                Some(spdx) => (
+                    format!("{}: {} in {}", import.url, spdx, license.path),
+                    spdx.as_str(),
+                ),
+                None => (
+                    format!(
+                        "{}: unrecognised license in {}, check it by hand",
+                        import.url, license.path
+                    ),
+                    "unrecognised",
+                ),
+            },
+            None => (
+                format!(
+                    "{}: no license file found, set license_file if it has one elsewhere",
+                    import.url
+                ),
+                "no license file",
+            ),
+        };
+        lines.push(line);
+        *counts.entry(spdx).or_insert(0) += 1;
+    }
+
+    let counts: Vec<String> = counts
+        .iter()
+        .map(|(spdx, count)| format!("{} {}", count, spdx))
+        .collect();
+    lines.push(format!(
+        "{} dependencies: {}",
+        lock.imports.len(),
+        counts.join(", ")
+    ));
+    lines
+}
+
+/// The SPDX identifier of the license in `text`, from an `SPDX-License-Identifier` line
+/// or else by recognising the text of a common license.
+pub(super) fn detect(text: &str) -> Option<String> {
+    if let Some(spdx) = text
+        .lines()
+        .find_map(|line| line.split("SPDX-License-Identifier:").nth(1))
+    {
+        return Some(spdx.trim().to_owned());
+    }
+
+    // License texts are wrapped wherever their authors liked
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    LICENSE_PHRASES
+        .iter()
+        .find(|(_, phrases)| phrases.iter().all(|phrase| text.contains(phrase)))
+        .map(|(spdx, _)| (*spdx).to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    const LOCK: &str = "---
+version: 2
+imports:
+  - branch: main
+    commit: 1111111111111111111111111111111111111111
+    url: \"https://github.com/acme/api.git\"
+    proto_dir: proto
+    proto_paths:
+      - acme/api
+    filename_regex: \".*\"
+    resolve_dependency: false
+    license:
+      path: LICENSE
+      vendored: licenses/github.com/acme/api/https/LICENSE
+      spdx: Apache-2.0
+  - branch: main
+    commit: 2222222222222222222222222222222222222222
+    url: \"https://github.com/acme/billing.git\"
+    proto_dir: proto
+    proto_paths:
+      - acme/billing
+    filename_regex: \".*\"
+    resolve_dependency: false
+    license:
+      path: COPYING
+      vendored: licenses/github.com/acme/billing/https/COPYING
+  - branch: main
+    commit: 3333333333333333333333333333333333333333
+    url: \"https://github.com/acme/common.git\"
+    proto_dir: proto
+    proto_paths:
+      - acme/common
+    filename_regex: \".*\"
+    resolve_dependency: false
+min_protovend_version: 4.2.0
+updated: \"2020-01-01T16:01:24Z\"
+";
+
+    #[test]
+    fn test_summarises_licenses() {
+        let lock = super::super::parse_lock(Path::new(PROTOVEND_LOCK), LOCK).unwrap();
+
+        assert_eq!(
+            vec![
+                "https://github.com/acme/api.git: Apache-2.0 in LICENSE",
+                "https://github.com/acme/billing.git: unrecognised license in COPYING, check it by hand",
+                "https://github.com/acme/common.git: no license file found, set license_file if it has one elsewhere",
+                "3 dependencies: 1 Apache-2.0, 1 no license file, 1 unrecognised",
+            ],
+            summarise(&lock)
+        );
+    }
+
+    #[test]
+    fn test_detects_licenses() {
+        assert_eq!(
+            Some(String::from("Apache-2.0")),
+            detect("\n                                 Apache License\n                           Version 2.0, January 2004\n")
+        );
+        assert_eq!(
+            Some(String::from("MIT")),
+            detect("MIT License\n\nPermission is hereby granted, free\nof charge, to any person")
+        );
+        assert_eq!(
+            Some(String::from("BSD-3-Clause")),
+            detect("Redistribution and use in source and binary forms, with or without\nmodification... Neither the name of the copyright holder")
+        );
+        assert_eq!(
+            Some(String::from("GPL-3.0-or-later")),
+            detect("Copyright Acme\nSPDX-License-Identifier: GPL-3.0-or-later\n")
+        );
+        assert_eq!(None, detect("All rights reserved.\n"));
+    }
+}
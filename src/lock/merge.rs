@@ -0,0 +1,287 @@
+/*
+ * Copyright 2020 Skyscanner Limited.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use super::{Import, ProtovendLock, LOCK_VERSION, PROTOVEND_LOCK};
+use crate::git;
+use crate::Result;
+use failure::format_err;
+use std::cmp;
+use std::fs;
+use std::path::Path;
+
+const OURS_MARKER: &str = "<<<<<<<";
+const BASE_MARKER: &str = "|||||||";
+const THEIRS_MARKER: &str = "=======";
+const END_MARKER: &str = ">>>>>>>";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Side {
+    Both,
+    Ours,
+    Base,
+    Theirs,
+}
+
+/// Resolves git conflict markers in `.protovend.lock` by merging the lock files on
+/// either side of them, and writes the result.
+pub fn resolve_conflicts() -> Result<ProtovendLock> {
+    let lock_file = Path::new(PROTOVEND_LOCK);
+    let contents = fs::read_to_string(lock_file)
+        .map_err(|e| format_err!("Cannot read {}: {}", PROTOVEND_LOCK, e))?;
+
+    match split_conflicts(&contents)? {
+        Some((ours, theirs)) => {
+            let ours = super::parse_lock(lock_file, &ours)?;
+            let theirs = super::parse_lock(lock_file, &theirs)?;
+            let mut lock = merge(ours, theirs, newer_import)?;
+            lock.write()
+                .map(|_| log::info!("Resolved merge conflicts in {}", PROTOVEND_LOCK))?;
+            Ok(lock)
+        }
+        None => {
+            log::info!("{} has no merge conflicts", PROTOVEND_LOCK);
+            super::load_lock()
+        }
+    }
+}
+
+/// Merges the lock file `theirs` into `ours`, as a git merge driver is expected to.
+pub fn merge_files(ours: &Path, theirs: &Path) -> Result<()> {
+    let mut lock = merge(
+        super::parse_lockfile(ours)?,
+        super::parse_lockfile(theirs)?,
+        newer_import,
+    )?;
+    lock.updated = super::timestamp()?;
+    lock.save(ours)
+}
+
+/// Splits `contents` into our and their side of its git conflict markers, or `None`
+/// when it has none.
+fn split_conflicts(contents: &str) -> Result<Option<(String, String)>> {
+    let mut side = Side::Both;
+    let mut conflicted = false;
+    let (mut ours, mut theirs) = (String::new(), String::new());
+
+    for line in contents.split_inclusive('\n') {
+        match side {
+            Side::Both if line.starts_with(OURS_MARKER) => {
+                side = Side::Ours;
+                conflicted = true;
+            }
+            Side::Ours if line.starts_with(BASE_MARKER) => side = Side::Base,
+            Side::Ours | Side::Base if line.starts_with(THEIRS_MARKER) => side = Side::Theirs,
+            Side::Theirs if line.starts_with(END_MARKER) => side = Side::Both,
+            Side::Both => {
+                ours.push_str(line);
+                theirs.push_str(line);
+            }
+            Side::Ours => ours.push_str(line),
+            Side::Base => {}
+            Side::Theirs => theirs.push_str(line),
+        }
+    }
+
+    if side != Side::Both {
+        Err(format_err!(
+            "{} has a merge conflict without an end marker",
+            PROTOVEND_LOCK
+        ))
+    } else if conflicted {
+        Ok(Some((ours, theirs)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Unions the imports of `ours` and `theirs`. Dependencies locked at different commits
+/// on each side are settled by `newer`, while those locked at the same commit must be
+/// identical.
+fn merge<F>(ours: ProtovendLock, theirs: ProtovendLock, newer: F) -> Result<ProtovendLock>
+where
+    F: Fn(Import, Import) -> Result<Import>,
+{
+    let mut imports = ours.imports;
+    for import in theirs.imports {
+        match imports.iter().position(|ours| ours.url == import.url) {
+            Some(position) if imports[position].commit != import.commit => {
+                let ours = imports.remove(position);
+                imports.push(newer(ours, import)?);
+            }
+            Some(position) if imports[position] != import => {
+                return Err(format_err!(
+                    "Cannot merge {} for {}: both sides lock {} but differ in how it is vendored. Pick one by hand",
+                    PROTOVEND_LOCK,
+                    import.url,
+                    import.commit
+                ))
+            }
+            Some(_) => {}
+            None => imports.push(import),
+        }
+    }
+    imports.sort_by(|a, b| a.url.cmp(&b.url));
+
+    Ok(ProtovendLock {
+        version: LOCK_VERSION,
+        imports,
+        min_protovend_version: cmp::max(ours.min_protovend_version, theirs.min_protovend_version),
+        updated: cmp::max(ours.updated, theirs.updated),
+    })
+}
+
+/// Whichever of `ours` and `theirs` locks a commit descending from the other's.
+fn newer_import(ours: Import, theirs: Import) -> Result<Import> {
+    // Both commits need to be in the cached clone to compare them
+    git::get_repo(&ours.url, &ours.branch, &ours.commit)?;
+    git::get_repo(&theirs.url, &theirs.branch, &theirs.commit)?;
+
+    if git::is_descendant(&ours.url, &theirs.commit, &ours.commit)? {
+        Ok(theirs)
+    } else if git::is_descendant(&ours.url, &ours.commit, &theirs.commit)? {
+        Ok(ours)
+    } else {
+        Err(format_err!(
+            "Cannot merge {} for {}: one side locks {} on {} and the other {} on {}, which have diverged. Pick one by hand",
+            PROTOVEND_LOCK,
+            ours.url,
+            ours.commit,
+            ours.branch,
+            theirs.commit,
+            theirs.branch
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFLICTED_LOCK: &str = "---
+version: 2
+imports:
+<<<<<<< HEAD
+  - branch: master
+    commit: 1111111111111111111111111111111111111111
+    url: \"https://github.com/user/a.git\"
+    proto_dir: proto
+    proto_paths:
+      - a
+    filename_regex: \".*\"
+    resolve_dependency: false
+||||||| merged common ancestors
+  - branch: master
+    commit: 0000000000000000000000000000000000000000
+    url: \"https://github.com/user/a.git\"
+    proto_dir: proto
+    proto_paths:
+      - a
+    filename_regex: \".*\"
+    resolve_dependency: false
+=======
+  - branch: master
+    commit: 2222222222222222222222222222222222222222
+    url: \"https://github.com/user/a.git\"
+    proto_dir: proto
+    proto_paths:
+      - a
+    filename_regex: \".*\"
+    resolve_dependency: false
+  - branch: main
+    commit: 3333333333333333333333333333333333333333
+    url: \"https://github.com/user/b.git\"
+    proto_dir: proto
+    proto_paths:
+      - b
+    filename_regex: \".*\"
+    resolve_dependency: false
+>>>>>>> feature
+min_protovend_version: 4.2.0
+<<<<<<< HEAD
+updated: \"2020-01-01T16:01:24Z\"
+=======
+updated: \"2020-01-02T16:01:24Z\"
+>>>>>>> feature
+";
+
+    fn sides() -> (ProtovendLock, ProtovendLock) {
+        let (ours, theirs) = split_conflicts(CONFLICTED_LOCK).unwrap().unwrap();
+        let lock_file = Path::new(PROTOVEND_LOCK);
+        (
+            super::super::parse_lock(lock_file, &ours).unwrap(),
+            super::super::parse_lock(lock_file, &theirs).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_splits_conflict_markers() {
+        let (ours, theirs) = sides();
+
+        assert_eq!(1, ours.imports.len());
+        assert_eq!(
+            "1111111111111111111111111111111111111111",
+            ours.imports[0].commit
+        );
+        assert_eq!(2, theirs.imports.len());
+        assert_eq!(
+            "2222222222222222222222222222222222222222",
+            theirs.imports[0].commit
+        );
+        assert!(split_conflicts("---\nversion: 2\n").unwrap().is_none());
+        assert!(split_conflicts("<<<<<<< HEAD\nimports: []\n=======\n").is_err());
+    }
+
+    #[test]
+    fn test_merges_imports_from_both_sides() {
+        let (ours, theirs) = sides();
+
+        let merged = merge(ours, theirs, |_, theirs| Ok(theirs)).unwrap();
+
+        let commits: Vec<&str> = merged.imports.iter().map(|i| i.commit.as_str()).collect();
+        assert_eq!(
+            vec![
+                "2222222222222222222222222222222222222222",
+                "3333333333333333333333333333333333333333"
+            ],
+            commits
+        );
+        assert_eq!("2020-01-02T16:01:24+00:00", merged.updated.to_rfc3339());
+    }
+
+    #[test]
+    fn test_fails_when_histories_diverge() {
+        let (ours, theirs) = sides();
+
+        let error = merge(ours, theirs, |ours, _| {
+            Err(format_err!("{} has diverged", ours.url))
+        })
+        .err()
+        .unwrap();
+
+        assert!(error.to_string().contains("has diverged"), "{}", error);
+    }
+
+    #[test]
+    fn test_fails_when_the_same_commit_is_vendored_differently() {
+        let (ours, mut theirs) = sides();
+        theirs.imports[0].commit = ours.imports[0].commit.clone();
+        theirs.imports[0].proto_paths.push(String::from("c"));
+
+        let error = merge(ours, theirs, |_, theirs| Ok(theirs)).err().unwrap();
+
+        assert!(error.to_string().contains("differ in how"), "{}", error);
+    }
+}
@@ -14,24 +14,74 @@
  * limitations under the License.
 */
 
-use super::Import;
+use super::{licenses, Import, License, VendoredFile};
+use crate::check;
+use crate::config::{OptionValue, ProtovendConfig};
+use crate::git;
+use crate::git_url::GitUrl;
+use crate::proto::{self, ProtoFile};
+use crate::util::{self, UnsafePath};
 use crate::Result;
-use crate::{check, git};
-use failure::format_err;
-use lazy_static::lazy_static;
+use failure::{format_err, Fail};
 use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
-use std::fs;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File};
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
 pub const PROTOS_OUTPUT_DIRECTORY: &str = "third_party/protovend";
 
-lazy_static! {
-    static ref PROTO_IMPORTS_RE: Regex = Regex::new(r#"import "([\w\\/\\.]+)";"#).unwrap();
+const LFS_POINTER_PREFIX: &str = "version https://git-lfs.github.com/spec/";
+
+/// License files looked for at the root of a dependency, in order.
+const LICENSE_FILES: [&str; 6] = [
+    "LICENSE",
+    "LICENSE.md",
+    "LICENSE.txt",
+    "COPYING",
+    "COPYING.md",
+    "COPYING.txt",
+];
+
+/// README files looked for at the root of a dependency, in order.
+const README_FILES: [&str; 4] = ["README.md", "README", "README.rst", "README.txt"];
+
+/// Directory under the vendored protos that each dependency's license and README are
+/// vendored into, in a directory of its own.
+const LICENSES_DIRECTORY: &str = "licenses";
+
+/// First line of the header `provenance_headers` adds to vendored files.
+const PROVENANCE_HEADER_START: &str = "// Code generated by protovend. DO NOT EDIT.\n";
+
+/// The files a proto file with `contents` imports, as written in it.
+pub(crate) fn proto_imports(contents: &str) -> Vec<&str> {
+    proto::imports(contents)
+        .into_iter()
+        .map(|path| &contents[path])
+        .collect()
 }
 
-pub(super) fn vendor_import(import: &Import) -> Result<()> {
+/// The contents of a vendored file as they were upstream, without any provenance header.
+pub(crate) fn upstream_contents(contents: &str) -> &str {
+    contents
+        .strip_prefix(PROVENANCE_HEADER_START)
+        .and_then(|header| header.split_once("\n\n"))
+        .map_or(contents, |(_, rest)| rest)
+}
+
+/// Vendored paths, each with the first vendored file importing it in sorted order, or
+/// `None` once it has matched a proto_path itself.
+type VendoredPaths = BTreeMap<String, Option<String>>;
+
+/// Vendors `import`, returning the files it copied sorted by path, its license and where
+/// its README went.
+pub(super) fn vendor_import(
+    import: &Import,
+    config: &ProtovendConfig,
+) -> Result<(Vec<VendoredFile>, Option<License>, Option<String>)> {
     log::info!(
         "Fetching proto files {} branch from git repo. Current: {}",
         import.branch,
@@ -39,9 +89,36 @@ pub(super) fn vendor_import(import: &Import) -> Result<()> {
     );
     let repo = git::get_repo(&import.url, &import.branch, &import.commit)?;
     let clone_location = repo.workdir().unwrap(); //Can unwrap safely as repository is not bare
+    git::verify_signature(
+        &import.url,
+        &import.branch,
+        &import.commit,
+        &config.trusted_keys,
+    )?;
+
+    // The lock file may have been edited by hand, so check again what config checked
+    util::safe_relative_path(&import.proto_dir)?;
+    let src_dir = &clone_location.join(&import.proto_dir);
+    util::confine(clone_location, src_dir)?;
 
+    if import.submodules {
+        let checked_out =
+            git::update_submodules(&repo, &import.proto_dir, |url| config.check_url(url))?;
+        if checked_out != import.submodule_commits {
+            return Err(format_err!(
+                "Submodules of {} at {} don't match {}, run protovend update to refresh it",
+                import.url,
+                import.commit,
+                super::PROTOVEND_LOCK
+            ));
+        }
+    }
+
+    let mut files = VendoredPaths::new();
+    let mut found_roots = BTreeSet::new();
     for proto_path in &import.proto_paths {
-        let src_dir = &clone_location.join(&import.proto_dir);
+        util::safe_relative_path(proto_path)?;
+        util::confine(src_dir, &src_dir.join(proto_path))?;
         log::info!(
             "calling check with {} and {}",
             clone_location.display(),
@@ -51,15 +128,161 @@ pub(super) fn vendor_import(import: &Import) -> Result<()> {
         let result = find_and_copy_protos(
             src_dir,
             proto_path,
-            &import.filename_regex,
-            import.resolve_dependency,
+            import,
+            config.provenance_headers,
+            &mut files,
         );
         match result {
-            Ok(res) => res,
+            Ok(roots) => found_roots.extend(roots),
+            Err(err)
+                if err.downcast_ref::<LfsPointer>().is_some()
+                    || err.downcast_ref::<UnsafePath>().is_some() =>
+            {
+                return Err(err)
+            }
             Err(err) => log::error!("{}", err),
         };
     }
-    Ok(())
+
+    let missing_roots: Vec<&str> = import
+        .roots
+        .iter()
+        .filter(|root| !found_roots.contains(*root))
+        .map(String::as_str)
+        .collect();
+    if !missing_roots.is_empty() {
+        return Err(format_err!(
+            "Cannot find roots {} of {} in any proto file under its proto_paths",
+            missing_roots.join(", "),
+            import.url
+        ));
+    }
+
+    let files = files
+        .into_iter()
+        .map(|(path, imported_by)| VendoredFile { path, imported_by })
+        .collect();
+    let directory = legal_directory(&import.url);
+    let license = vendor_license(import, clone_location, &directory)?;
+    let readme = vendor_readme(clone_location, &directory)?;
+    Ok((files, license, readme))
+}
+
+/// Where the license and README of the dependency on `url` are vendored. The same
+/// repository can be a dependency over several protocols, or at several refs, so these
+/// tell its directories apart.
+fn legal_directory(url: &GitUrl) -> String {
+    let mut dependency = url.scheme().unwrap_or("ssh").to_owned();
+    if let Some(reference) = url.reference() {
+        dependency.push('@');
+        dependency.push_str(&reference.replace('/', "_"));
+    }
+    format!(
+        "{}/{}/{}/{}",
+        LICENSES_DIRECTORY,
+        url.host(),
+        url.path(),
+        dependency
+    )
+}
+
+/// Copies the license file of the dependency into `directory`, returning the license
+/// found.
+fn vendor_license(
+    import: &Import,
+    clone_location: &Path,
+    directory: &str,
+) -> Result<Option<License>> {
+    let license_file = match &import.license_file {
+        Some(license_file) => {
+            let path = util::safe_relative_path(license_file)?;
+            if !clone_location.join(&path).is_file() {
+                return Err(format_err!(
+                    "Cannot find license_file {} in {}",
+                    license_file,
+                    import.url
+                ));
+            }
+            Some(path)
+        }
+        None => LICENSE_FILES
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| clone_location.join(path).is_file()),
+    };
+    let path = match license_file {
+        Some(path) => path,
+        None => {
+            log::warn!(
+                "Cannot find a license file in {}, set license_file if it has one elsewhere",
+                import.url
+            );
+            return Ok(None);
+        }
+    };
+
+    let source = clone_location.join(&path);
+    util::confine(clone_location, &source)?;
+    let vendored = copy_to_directory(&source, directory)?;
+
+    let text = fs::read(&source)?;
+    Ok(Some(License {
+        path: path.to_string_lossy().replace('\\', "/"),
+        vendored,
+        spdx: licenses::detect(&String::from_utf8_lossy(&text)),
+    }))
+}
+
+/// Copies the README at the root of the dependency into `directory`, returning where it
+/// went.
+fn vendor_readme(clone_location: &Path, directory: &str) -> Result<Option<String>> {
+    match README_FILES
+        .iter()
+        .map(|readme| clone_location.join(readme))
+        .find(|readme| readme.is_file())
+    {
+        Some(source) => {
+            util::confine(clone_location, &source)?;
+            copy_to_directory(&source, directory).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Copies `source` into `directory` under the vendored protos, returning the path of the
+/// copy relative to them.
+fn copy_to_directory(source: &Path, directory: &str) -> Result<String> {
+    let vendored = format!(
+        "{}/{}",
+        directory,
+        source.file_name().unwrap().to_string_lossy()
+    );
+    let dest_file = create_dest_folder_path(&vendored)?;
+    fs::create_dir_all(dest_file.parent().unwrap())?;
+    fs::copy(source, &dest_file)?;
+    Ok(vendored)
+}
+
+/// A file that git-lfs replaced with a pointer, which protovend can't resolve.
+#[derive(Debug)]
+struct LfsPointer(PathBuf);
+
+impl Fail for LfsPointer {}
+
+impl Display for LfsPointer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is a Git LFS pointer rather than a proto file. Commit proto files to git directly instead of tracking them with LFS",
+            self.0.display()
+        )
+    }
+}
+
+fn is_lfs_pointer(path: &Path) -> Result<bool> {
+    let mut start = [0; LFS_POINTER_PREFIX.len()];
+    let read = File::open(path)?.read(&mut start)?;
+    Ok(start[..read] == *LFS_POINTER_PREFIX.as_bytes())
 }
 
 pub(super) fn prepare_output_directory() -> Result<()> {
@@ -82,9 +305,10 @@ fn create_dest_folder_path(repo: &str) -> Result<PathBuf> {
 fn find_and_copy_protos(
     src_dir: &Path,
     proto_path: &str,
-    filename_regex: &str,
-    resolve_dependency: bool,
-) -> Result<()> {
+    import: &Import,
+    provenance_headers: bool,
+    files: &mut VendoredPaths,
+) -> Result<Vec<String>> {
     let src_folder = &src_dir.join(Path::new(proto_path));
     if !src_folder.exists() {
         return Err(format_err!(
@@ -93,31 +317,126 @@ fn find_and_copy_protos(
         ));
     }
 
-    let re = Regex::new(filename_regex).unwrap();
+    let (packages, roots) = (&import.packages, &import.roots);
+    let re = Regex::new(&import.filename_regex).unwrap();
+    let mut found_roots = Vec::new();
     for entry in WalkDir::new(src_folder) {
         let entry = entry?;
-        if entry.metadata()?.is_file()
+        // A symlink could lead anywhere, so only follow those that stay in the dependency
+        if entry.path_is_symlink() {
+            util::confine(src_dir, entry.path())?;
+        }
+        if entry.path().is_file()
             && entry.file_name().to_string_lossy().ends_with(".proto")
             && re.is_match(entry.path().file_stem().unwrap().to_str().unwrap())
         {
-            copy_protos(src_dir, proto_path, entry.path(), resolve_dependency)?;
+            let file = if packages.is_empty() && roots.is_empty() {
+                ProtoFile::default()
+            } else {
+                proto::parse(&fs::read_to_string(entry.path())?)
+            };
+            if !packages.is_empty() && !in_packages(packages, file.package.as_deref()) {
+                continue;
+            }
+            if roots.is_empty() {
+                copy_protos(
+                    src_dir,
+                    proto_path,
+                    entry.path(),
+                    import,
+                    provenance_headers,
+                    import.resolve_dependency,
+                    None,
+                    files,
+                )?;
+                continue;
+            }
+
+            // Only files defining a root are vendored, along with whatever they import
+            let defined_roots: Vec<String> = file
+                .definitions
+                .into_iter()
+                .filter(|definition| roots.contains(definition))
+                .collect();
+            if !defined_roots.is_empty() {
+                copy_protos(
+                    src_dir,
+                    proto_path,
+                    entry.path(),
+                    import,
+                    provenance_headers,
+                    true,
+                    None,
+                    files,
+                )?;
+                found_roots.extend(defined_roots);
+            }
         }
     }
 
-    Ok(())
+    Ok(found_roots)
+}
+
+/// Whether `package` is one of `packages`, or inside one given as `package.*`.
+fn in_packages(packages: &[String], package: Option<&str>) -> bool {
+    let package = package.unwrap_or_default();
+    packages
+        .iter()
+        .any(|pattern| match pattern.strip_suffix(".*") {
+            Some(parent) => package
+                .strip_prefix(parent)
+                .map_or(false, |subpackage| subpackage.starts_with('.')),
+            None => package == pattern,
+        })
 }
 
+#[allow(clippy::too_many_arguments)]
 fn copy_protos(
     src_dir: &Path,
     proto_path: &str,
     proto_file_path: &Path,
+    import: &Import,
+    provenance_headers: bool,
     resolve_dependency: bool,
+    imported_by: Option<&str>,
+    files: &mut VendoredPaths,
 ) -> Result<()> {
-    let dest_folder = create_dest_folder_path(proto_path)?;
-    let relative_path = proto_file_path.strip_prefix(src_dir.join(&proto_path))?;
-    let dest_file = dest_folder.join(relative_path);
+    if is_lfs_pointer(proto_file_path)? {
+        return Err(LfsPointer(proto_file_path.to_owned()).into());
+    }
+
+    let relative_path = proto_file_path.strip_prefix(src_dir.join(proto_path))?;
+    let upstream_path = Path::new(proto_path)
+        .join(relative_path)
+        .to_string_lossy()
+        .replace('\\', "/");
+    let vendored_path = relocated(&import.relocate, &upstream_path);
+    // The lock file may have been edited by hand, so check again what config checked
+    util::safe_relative_path(&vendored_path)?;
+    let imported_by = imported_by.map(String::from);
+    if let Some(recorded) = files.get_mut(&vendored_path) {
+        // Already copied along with its imports, which also keeps import cycles finite
+        if imported_by < *recorded {
+            *recorded = imported_by;
+        }
+        return Ok(());
+    }
+    files.insert(vendored_path.clone(), imported_by);
+
+    let dest_file = create_dest_folder_path(&vendored_path)?;
     fs::create_dir_all(dest_file.parent().unwrap())?;
-    fs::copy(proto_file_path, &dest_file)?;
+    if import.options.is_empty() && import.relocate.is_empty() && !provenance_headers {
+        fs::copy(proto_file_path, &dest_file)?;
+    } else {
+        let contents = fs::read_to_string(proto_file_path)?;
+        let contents = relocate_imports(&contents, &import.relocate);
+        let directory = vendored_path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        let mut contents = set_options(&contents, &import.options, directory);
+        if provenance_headers {
+            contents.insert_str(0, &provenance_header(import, &upstream_path)?);
+        }
+        fs::write(&dest_file, contents)?;
+    }
     log::debug!(
         "Copied {} to {}",
         proto_file_path.display(),
@@ -126,16 +445,22 @@ fn copy_protos(
 
     if resolve_dependency {
         let file_content = fs::read_to_string(proto_file_path)?;
-        for cap in PROTO_IMPORTS_RE.captures_iter(file_content.as_str()) {
-            let import_path = Path::new(&src_dir).join(&cap[1]);
+        for imported in proto_imports(&file_content) {
+            let imported_file = util::safe_relative_path(imported)?;
+            let import_path = src_dir.join(&imported_file);
             if import_path.exists() {
-                log::debug!("Found an imported dependency {}", &cap[1]);
-                let import_proto_path = Path::new(&cap[1]).parent().unwrap();
+                util::confine(src_dir, &import_path)?;
+                log::debug!("Found an imported dependency {}", imported);
+                let import_proto_path = imported_file.parent().unwrap();
                 copy_protos(
                     src_dir,
                     import_proto_path.to_str().unwrap(),
                     &import_path,
+                    import,
+                    provenance_headers,
                     resolve_dependency,
+                    Some(&vendored_path),
+                    files,
                 )?;
             } else {
                 log::debug!(
@@ -148,3 +473,313 @@ fn copy_protos(
 
     Ok(())
 }
+
+/// Says where the file at `upstream_path` under `proto_dir` of `import` came from.
+fn provenance_header(import: &Import, upstream_path: &str) -> Result<String> {
+    let path = util::safe_relative_path(&import.proto_dir)?.join(upstream_path);
+    Ok(format!(
+        "{}// Managed by protovend, run protovend install or update to change it.\
+         \n// source: {}\
+         \n// commit: {}\
+         \n// path: {}\n\n",
+        PROVENANCE_HEADER_START,
+        import.url,
+        import.commit,
+        path.to_string_lossy().replace('\\', "/")
+    ))
+}
+
+/// Where the file at `path` upstream is vendored, moved by the longest prefix of its
+/// directories in `relocate`.
+fn relocated(relocate: &BTreeMap<String, String>, path: &str) -> String {
+    relocate
+        .iter()
+        .filter_map(|(from, to)| {
+            let from = from.trim_end_matches('/');
+            let rest = path.strip_prefix(from)?.strip_prefix('/')?;
+            Some((from.len(), Path::new(to).join(rest)))
+        })
+        .max_by_key(|(matched, _)| *matched)
+        .map_or_else(
+            || path.to_owned(),
+            |(_, moved)| moved.to_string_lossy().replace('\\', "/"),
+        )
+}
+
+/// The upstream paths that `relocate` would vendor to `path`, including `path` itself
+/// when it isn't relocated.
+pub(super) fn unrelocated(relocate: &BTreeMap<String, String>, path: &str) -> Vec<String> {
+    let candidates = relocate.iter().filter_map(|(from, to)| {
+        let to = to.trim_end_matches('/');
+        let rest = if to.is_empty() {
+            path
+        } else {
+            path.strip_prefix(to)?.strip_prefix('/')?
+        };
+        Some(format!("{}/{}", from.trim_end_matches('/'), rest))
+    });
+    std::iter::once(path.to_owned())
+        .chain(candidates)
+        .filter(|candidate| relocated(relocate, candidate) == path)
+        .collect()
+}
+
+/// Rewrites the imports in `contents` to wherever `relocate` vendors them.
+fn relocate_imports(contents: &str, relocate: &BTreeMap<String, String>) -> String {
+    // Only the path changes, keeping any public or weak and the quotes as they were
+    let mut relocated_contents = String::with_capacity(contents.len());
+    let mut end = 0;
+    for path in proto::imports(contents) {
+        relocated_contents.push_str(&contents[end..path.start]);
+        relocated_contents.push_str(&relocated(relocate, &contents[path.clone()]));
+        end = path.end;
+    }
+    relocated_contents.push_str(&contents[end..]);
+    relocated_contents
+}
+
+/// Applies `options` to a file vendored into `directory`, filling in their templates.
+fn set_options(
+    contents: &str,
+    options: &BTreeMap<String, Option<OptionValue>>,
+    directory: &str,
+) -> String {
+    let package = proto::parse(contents).package.unwrap_or_default();
+    options
+        .iter()
+        .fold(contents.to_owned(), |contents, (name, value)| {
+            let value = value.as_ref().map(|value| match value {
+                OptionValue::Bool(value) => value.to_string(),
+                OptionValue::Text(template) => {
+                    let text = template
+                        .replace("{package}", &package)
+                        .replace("{path}", directory);
+                    proto::quote(&text)
+                }
+                OptionValue::Enum { value } => value.clone(),
+            });
+            proto::set_option(&contents, name, value.as_deref())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+
+    fn import() -> Import {
+        serde_yaml::from_str(
+            "url: https://github.com/acme/api.git\
+             \nbranch: master\
+             \ncommit: 0123456789abcdef0123456789abcdef01234567\
+             \nproto_dir: .\
+             \nproto_paths: [path/to]\
+             \nfilename_regex: \".*\"\
+             \nresolve_dependency: false\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_detects_lfs_pointers() {
+        let pointer = tests_utils::fs::write_contents_to_temp_file(
+            "version https://git-lfs.github.com/spec/v1\
+             \noid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\
+             \nsize 12345\n",
+            "pointer.proto",
+        );
+        let proto =
+            tests_utils::fs::write_contents_to_temp_file("syntax = \"proto3\";\n", "message.proto");
+
+        assert!(is_lfs_pointer(&pointer).unwrap());
+        assert!(!is_lfs_pointer(&proto).unwrap());
+    }
+
+    #[test]
+    fn test_matches_packages() {
+        let packages = vec![
+            String::from("acme.payments.v1"),
+            String::from("acme.common.*"),
+        ];
+
+        assert!(in_packages(&packages, Some("acme.payments.v1")));
+        assert!(!in_packages(&packages, Some("acme.payments.v2")));
+        assert!(in_packages(&packages, Some("acme.common.money")));
+        assert!(in_packages(&packages, Some("acme.common.money.v1")));
+        assert!(!in_packages(&packages, Some("acme.common")));
+        assert!(!in_packages(&packages, Some("acme.commons.money")));
+        assert!(!in_packages(&packages, None));
+    }
+
+    #[test]
+    fn test_strips_provenance_headers() {
+        let upstream = "// Comments upstream\n\nsyntax = \"proto3\";\n";
+        let header = provenance_header(&import(), "path/to/price.proto").unwrap();
+
+        assert_eq!(
+            "// Code generated by protovend. DO NOT EDIT.\
+             \n// Managed by protovend, run protovend install or update to change it.\
+             \n// source: https://github.com/acme/api.git\
+             \n// commit: 0123456789abcdef0123456789abcdef01234567\
+             \n// path: path/to/price.proto\n\n",
+            header
+        );
+        assert_eq!(
+            upstream,
+            upstream_contents(&format!("{}{}", header, upstream))
+        );
+        assert_eq!(upstream, upstream_contents(upstream));
+    }
+
+    #[test]
+    fn test_relocates_by_longest_prefix() {
+        let relocate = serde_yaml::from_str(
+            "acme: vendor/acme\
+             \nacme/api/: public/api\
+             \ngoogle/type: \"\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            "public/api/v1/price.proto",
+            relocated(&relocate, "acme/api/v1/price.proto")
+        );
+        assert_eq!(
+            "vendor/acme/money.proto",
+            relocated(&relocate, "acme/money.proto")
+        );
+        assert_eq!(
+            "money.proto",
+            relocated(&relocate, "google/type/money.proto")
+        );
+        assert_eq!(
+            "acmes/money.proto",
+            relocated(&relocate, "acmes/money.proto")
+        );
+        assert_eq!(
+            "syntax = \"proto3\";\
+             \nimport \"public/api/v1/price.proto\";\
+             \nimport \"google/protobuf/timestamp.proto\";\n",
+            relocate_imports(
+                "syntax = \"proto3\";\
+                 \nimport \"acme/api/v1/price.proto\";\
+                 \nimport \"google/protobuf/timestamp.proto\";\n",
+                &relocate
+            )
+        );
+    }
+
+    #[test]
+    fn test_vendors_licenses_of_each_dependency_apart() {
+        let directory = |url: &str| legal_directory(&url.parse().unwrap());
+
+        assert_eq!(
+            "licenses/github.com/acme/api/https",
+            directory("https://github.com/acme/api.git")
+        );
+        assert_eq!(
+            "licenses/github.com/acme/api/ssh",
+            directory("git@github.com:acme/api.git")
+        );
+        assert_eq!(
+            "licenses/github.com/acme/api/https@release_v1",
+            directory("https://github.com/acme/api.git#release/v1")
+        );
+    }
+
+    #[test]
+    fn test_maps_relocated_paths_back() {
+        let relocate = serde_yaml::from_str(
+            "acme: vendor/acme\
+             \ngoogle/type: \"\"\n",
+        )
+        .unwrap();
+
+        assert!(unrelocated(&relocate, "vendor/acme/api/price.proto")
+            .contains(&String::from("acme/api/price.proto")));
+        assert_eq!(
+            vec!["money.proto", "google/type/money.proto"],
+            unrelocated(&relocate, "money.proto")
+        );
+        // Upstream, acme/money.proto itself is vendored elsewhere
+        assert_eq!(
+            vec!["google/type/acme/money.proto"],
+            unrelocated(&relocate, "acme/money.proto")
+        );
+    }
+
+    #[test]
+    fn test_relocates_public_and_weak_imports() {
+        let relocate = serde_yaml::from_str("acme: vendor/acme\n").unwrap();
+        let contents = "import public \"acme/money.proto\";\
+                        \nimport  weak 'acme/price.proto' ;\
+                        \nimport\t\"acme/v1/quote-v1.proto\";\n";
+
+        assert_eq!(
+            vec![
+                "acme/money.proto",
+                "acme/price.proto",
+                "acme/v1/quote-v1.proto"
+            ],
+            proto_imports(contents)
+        );
+        assert_eq!(
+            "import public \"vendor/acme/money.proto\";\
+             \nimport  weak 'vendor/acme/price.proto' ;\
+             \nimport\t\"vendor/acme/v1/quote-v1.proto\";\n",
+            relocate_imports(contents, &relocate)
+        );
+    }
+
+    #[test]
+    fn test_sets_options_from_templates() {
+        let options = serde_yaml::from_str(
+            "go_package: \"example.com/gen/{path}\"\
+             \njava_package: com.example.{package}\
+             \njava_multiple_files: true\
+             \noptimize_for: {enum: SPEED}\
+             \ncsharp_namespace: ~\n",
+        )
+        .unwrap();
+        let contents = "syntax = \"proto3\";\
+                        \npackage acme.api.v1;\
+                        \noption csharp_namespace = \"Acme.Api.V1\";\
+                        \noption go_package = \"github.com/acme/api/v1;api\";\n";
+
+        assert_eq!(
+            "syntax = \"proto3\";\
+             \npackage acme.api.v1;\
+             \noption optimize_for = SPEED;\
+             \noption java_package = \"com.example.acme.api.v1\";\
+             \noption java_multiple_files = true;\
+             \noption go_package = \"example.com/gen/acme/api/v1\";\n",
+            set_options(contents, &options, "acme/api/v1")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_refuses_symlinks_leading_outside_the_dependency() {
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret.proto"), "").unwrap();
+        let src_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(src_dir.path().join("path/to")).unwrap();
+        std::os::unix::fs::symlink(
+            outside.path().join("secret.proto"),
+            src_dir.path().join("path/to/evil.proto"),
+        )
+        .unwrap();
+
+        let error = find_and_copy_protos(
+            src_dir.path(),
+            "path/to",
+            &import(),
+            false,
+            &mut VendoredPaths::new(),
+        )
+        .err()
+        .unwrap();
+
+        assert!(error.downcast_ref::<UnsafePath>().is_some(), "{}", error);
+    }
+}
@@ -0,0 +1,260 @@
+/*
+ * Copyright 2020 Skyscanner Limited.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use super::vendor::{self, PROTOS_OUTPUT_DIRECTORY};
+use super::{Import, ProtovendLock, PROTOVEND_LOCK};
+use crate::util;
+use crate::Result;
+use failure::format_err;
+use std::env;
+use std::path::Path;
+
+/// Logs which dependency vendored `file`, and how.
+pub fn why(lock: &ProtovendLock, file: &Path) -> Result<()> {
+    for line in explain(lock, file)? {
+        log::info!("{}", line);
+    }
+    Ok(())
+}
+
+/// Describes each import that vendored `file`, following `imported_by` back to the
+/// file that matched a proto_path when it was pulled in as an import, by resolve_dependency
+/// or roots.
+fn explain(lock: &ProtovendLock, file: &Path) -> Result<Vec<String>> {
+    let path = vendored_path(file)?;
+    let mut lines = Vec::new();
+
+    for import in lock
+        .imports
+        .iter()
+        .filter(|import| import.files.iter().any(|f| f.path == path))
+    {
+        lines.push(format!(
+            "{} was vendored from {} at commit {} of {}",
+            path, import.url, import.commit, import.branch
+        ));
+
+        let chain = import_chain(import, &path);
+        let root = chain.last().unwrap(); //Always holds path itself
+        if chain.len() > 1 {
+            let chain: Vec<&str> = chain.iter().rev().map(String::as_str).collect();
+            // Files defining roots have their imports vendored whatever resolve_dependency says
+            let pulled_in_by = if import.roots.is_empty() {
+                String::from("resolve_dependency")
+            } else {
+                format!("imports of roots {}", import.roots.join(", "))
+            };
+            lines.push(format!(
+                "It was pulled in by {} through {}",
+                pulled_in_by,
+                chain.join(" -> ")
+            ));
+        }
+        // proto_paths are upstream paths, which relocate may have moved the file from
+        let upstream_paths = vendor::unrelocated(&import.relocate, root);
+        let matched = upstream_paths
+            .iter()
+            .flat_map(|upstream| {
+                import
+                    .proto_paths
+                    .iter()
+                    .filter(move |proto_path| Path::new(upstream).starts_with(proto_path))
+                    .map(move |proto_path| (upstream, proto_path))
+            })
+            .max_by_key(|(_, proto_path)| proto_path.len());
+        match matched {
+            Some((upstream, proto_path)) => {
+                if upstream != root {
+                    lines.push(format!("It was relocated from {}", upstream));
+                }
+                lines.push(format!(
+                    "{} matches proto_path {} under proto_dir {} and {}",
+                    upstream,
+                    proto_path,
+                    import.proto_dir,
+                    filters(import).join(" and ")
+                ))
+            }
+            None => lines.push(format!(
+                "{} is not under any proto_path of {} any more, run protovend install to refresh {}",
+                root, import.url, PROTOVEND_LOCK
+            )),
+        }
+    }
+
+    if lines.is_empty() {
+        Err(format_err!(
+            "{} was not vendored by any dependency in {}. Run protovend install if it is out of date",
+            path,
+            PROTOVEND_LOCK
+        ))
+    } else {
+        Ok(lines)
+    }
+}
+
+/// The filters besides proto_path that files of `import` are vendored through.
+fn filters(import: &Import) -> Vec<String> {
+    let mut filters = vec![format!("filename_regex {}", import.filename_regex)];
+    if !import.packages.is_empty() {
+        filters.push(format!("packages {}", import.packages.join(", ")));
+    }
+    if !import.roots.is_empty() {
+        filters.push(format!("roots {}", import.roots.join(", ")));
+    }
+    filters
+}
+
+/// `path` followed by the files that imported it in turn, ending with the one that
+/// matched a proto_path.
+fn import_chain(import: &Import, path: &str) -> Vec<String> {
+    let mut chain = vec![path.to_owned()];
+    let importer_of = |path: &str| {
+        import
+            .files
+            .iter()
+            .find(|f| f.path == path)
+            .and_then(|f| f.imported_by.clone())
+    };
+    while let Some(importer) = importer_of(chain.last().unwrap()) {
+        // A hand edited lock file could loop
+        if chain.contains(&importer) {
+            break;
+        }
+        chain.push(importer);
+    }
+    chain
+}
+
+/// `file` relative to the vendored proto directory, as the lock file records it.
+fn vendored_path(file: &Path) -> Result<String> {
+    let current_dir = env::current_dir()?;
+    let file = file.strip_prefix(&current_dir).unwrap_or(file);
+    let file = util::safe_relative_path(&file.to_string_lossy())?;
+    let file = file.strip_prefix(PROTOS_OUTPUT_DIRECTORY).unwrap_or(&file);
+    Ok(file.to_string_lossy().replace('\\', "/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCK: &str = "---
+version: 2
+imports:
+  - branch: main
+    commit: 1111111111111111111111111111111111111111
+    url: \"https://github.com/acme/api.git\"
+    proto_dir: proto
+    proto_paths:
+      - acme/api
+    filename_regex: \".*\"
+    resolve_dependency: true
+    files:
+      - path: acme/api/service.proto
+      - path: acme/common/money.proto
+        imported_by: acme/common/types.proto
+      - path: acme/common/types.proto
+        imported_by: acme/api/service.proto
+min_protovend_version: 4.2.0
+updated: \"2020-01-01T16:01:24Z\"
+";
+
+    fn lock() -> ProtovendLock {
+        super::super::parse_lock(Path::new(PROTOVEND_LOCK), LOCK).unwrap()
+    }
+
+    #[test]
+    fn test_explains_declared_files() {
+        let lines = explain(
+            &lock(),
+            Path::new("./third_party/protovend/acme/api/service.proto"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                "acme/api/service.proto was vendored from https://github.com/acme/api.git at commit 1111111111111111111111111111111111111111 of main",
+                "acme/api/service.proto matches proto_path acme/api under proto_dir proto and filename_regex .*",
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn test_explains_import_chain() {
+        let lines = explain(&lock(), Path::new("acme/common/money.proto")).unwrap();
+
+        assert_eq!(
+            "It was pulled in by resolve_dependency through acme/api/service.proto -> acme/common/types.proto -> acme/common/money.proto",
+            lines[1]
+        );
+        assert!(lines[2].starts_with("acme/api/service.proto matches proto_path acme/api"));
+    }
+
+    #[test]
+    fn test_explains_packages_and_roots() {
+        let mut lock = lock();
+        lock.imports[0].packages = vec![String::from("acme.api.*")];
+        lock.imports[0].roots = vec![String::from("acme.api.Pricing")];
+
+        let lines = explain(&lock, Path::new("acme/api/service.proto")).unwrap();
+
+        assert_eq!(
+            "acme/api/service.proto matches proto_path acme/api under proto_dir proto and filename_regex .* and packages acme.api.* and roots acme.api.Pricing",
+            lines[1]
+        );
+    }
+
+    #[test]
+    fn test_explains_files_pulled_in_by_roots() {
+        let mut lock = lock();
+        lock.imports[0].roots = vec![String::from("acme.api.Pricing")];
+        lock.imports[0].resolve_dependency = false;
+
+        let lines = explain(&lock, Path::new("acme/common/money.proto")).unwrap();
+
+        assert_eq!(
+            "It was pulled in by imports of roots acme.api.Pricing through acme/api/service.proto -> acme/common/types.proto -> acme/common/money.proto",
+            lines[1]
+        );
+    }
+
+    #[test]
+    fn test_explains_relocated_files() {
+        let mut lock = lock();
+        let import = &mut lock.imports[0];
+        import
+            .relocate
+            .insert(String::from("acme/api"), String::from("vendor/api"));
+        import.files[0].path = String::from("vendor/api/service.proto");
+        import.files[2].imported_by = Some(String::from("vendor/api/service.proto"));
+
+        let lines = explain(&lock, Path::new("acme/common/money.proto")).unwrap();
+
+        assert_eq!("It was relocated from acme/api/service.proto", lines[2]);
+        assert!(lines[3].starts_with("acme/api/service.proto matches proto_path acme/api"));
+    }
+
+    #[test]
+    fn test_fails_for_files_not_vendored() {
+        let error = explain(&lock(), Path::new("acme/other.proto"))
+            .err()
+            .unwrap();
+
+        assert!(error.to_string().contains("was not vendored"), "{}", error);
+    }
+}
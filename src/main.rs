@@ -16,6 +16,9 @@
 
 use human_panic::setup_panic;
 use protovend::git_url::GitUrl;
+use protovend::settings::Backend;
+use protovend::GraphFormat;
+use std::path::PathBuf;
 use structopt::clap::ArgGroup;
 use structopt::StructOpt;
 
@@ -28,6 +31,18 @@ struct Protovend {
     ///Prints debug logs. Used for diagnostics only.
     #[structopt(long, group = "level")]
     debug: bool,
+    ///Seconds before a git clone or fetch is abandoned, 0 waits forever.
+    #[structopt(long)]
+    git_timeout: Option<u64>,
+    ///Number of times a git clone or fetch is retried after a network failure.
+    #[structopt(long)]
+    git_retries: Option<u32>,
+    ///How to talk to git remotes: "cli" runs the git executable, "library" uses libgit2.
+    #[structopt(long)]
+    git_backend: Option<Backend>,
+    ///Vendors from hosts that allowed_hosts would otherwise refuse.
+    #[structopt(long)]
+    allow_any_host: bool,
     #[structopt(subcommand)]
     sub: Subcommand,
 }
@@ -40,9 +55,10 @@ enum Subcommand {
     ///Add a given git repo to projects metadata file
     Add {
         url: GitUrl,
-        /// Git branch to checkout proto files from
-        #[structopt(short, long, default_value = "master")]
-        branch: String,
+        /// Git branch or tag to checkout proto files from, defaults to the URL's #ref or else the
+        /// remote's default branch
+        #[structopt(short, long)]
+        branch: Option<String>,
         /// Directory to search for proto files
         #[structopt(short = "d", long, default_value = "proto")]
         proto_dir: String,
@@ -55,15 +71,41 @@ enum Subcommand {
         /// Whether or not to resolve dependencies in proto files
         #[structopt(short, long)]
         resolve_dependency: bool,
+        /// Whether or not to check out git submodules under proto_dir
+        #[structopt(long)]
+        submodules: bool,
     },
     ///Update one or all repos in protovend metadata file to latest version
     Update { repo: Option<GitUrl> },
     ///Install copies of protofiles declared in projects metadata file
     Install {},
+    ///Explain which dependency vendored a file under third_party/protovend, and why
+    Why { file: PathBuf },
+    ///Print the import graph of vendored proto files, grouped by dependency
+    Graph {
+        /// Output format: "dot" for Graphviz or "json"
+        #[structopt(long, default_value = "dot")]
+        format: GraphFormat,
+    },
+    ///Summarise the licenses of vendored dependencies
+    Licenses {},
+    ///Work with the protovend lock file
+    Lock(LockSubcommand),
+    ///Rewrite protovend metadata files written by older versions in the current format
+    Migrate {},
     ///Delete all locally cached repos stored in protovend folder
     Cleanup {},
 }
 
+#[derive(StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+enum LockSubcommand {
+    ///Resolve git merge conflicts in the lock file and install the result
+    Resolve {},
+    ///Merge their lock file into ours, for use as a git merge driver
+    Merge { ours: PathBuf, theirs: PathBuf },
+}
+
 fn setup_logger(level: log::LevelFilter) -> std::result::Result<(), fern::InitError> {
     fern::Dispatch::new()
         .format(|out, message, record| out.finish(format_args!("({}) {}", record.level(), message)))
@@ -84,6 +126,14 @@ fn run_command(opts: Protovend) -> protovend::Result<()> {
 
     setup_logger(level)?;
 
+    let mut settings =
+        protovend::settings::load()?.with_overrides(opts.git_timeout, opts.git_retries);
+    if let Some(backend) = opts.git_backend {
+        settings.git.backend = backend;
+    }
+    settings.allow_any_host = opts.allow_any_host;
+    protovend::settings::configure(settings);
+
     match opts.sub {
         Subcommand::Init {} => protovend::init(),
         Subcommand::Add {
@@ -93,6 +143,7 @@ fn run_command(opts: Protovend) -> protovend::Result<()> {
             proto_path,
             filename_regex,
             resolve_dependency,
+            submodules,
         } => protovend::add(
             url,
             branch,
@@ -100,13 +151,24 @@ fn run_command(opts: Protovend) -> protovend::Result<()> {
             proto_path,
             filename_regex,
             resolve_dependency,
+            submodules,
         ),
         Subcommand::Update { repo } => protovend::update(repo),
         Subcommand::Install {} => protovend::install(),
+        Subcommand::Graph { format } => protovend::graph(format),
+        Subcommand::Why { file } => protovend::why(&file),
+        Subcommand::Licenses {} => protovend::licenses(),
+        Subcommand::Lock(LockSubcommand::Resolve {}) => protovend::resolve_lock(),
+        Subcommand::Lock(LockSubcommand::Merge { ours, theirs }) => {
+            protovend::merge_lock(&ours, &theirs)
+        }
+        Subcommand::Migrate {} => protovend::migrate(),
         Subcommand::Cleanup {} => protovend::cleanup(),
     }
 }
 
+// human-panic 1.x still names the pre-1.81 PanicInfo alias
+#[allow(deprecated)]
 fn main() {
     setup_panic!();
 
@@ -0,0 +1,436 @@
+/*
+ * Copyright 2020 Skyscanner Limited.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::ops::Range;
+
+/// What protovend needs to know about a proto file's contents.
+#[derive(Debug, Default, PartialEq)]
+pub struct ProtoFile {
+    pub package: Option<String>,
+    /// Fully qualified names of the messages, enums and services it defines, including
+    /// nested ones.
+    pub definitions: Vec<String>,
+}
+
+/// Parses the declarations protovend cares about out of a proto file, skipping over
+/// everything else.
+pub fn parse(contents: &str) -> ProtoFile {
+    let tokens: Vec<&str> = tokenize(contents).into_iter().map(|(_, t)| t).collect();
+    let mut file = ProtoFile::default();
+    // The name each open block defines, if any
+    let mut scopes: Vec<Option<String>> = Vec::new();
+
+    let mut i = 0;
+    while i < tokens.len() {
+        match (tokens[i], tokens.get(i + 1), tokens.get(i + 2)) {
+            ("package", Some(package), Some(&";")) if scopes.is_empty() => {
+                file.package = Some((*package).to_owned());
+                i += 3;
+            }
+            ("message", Some(name), Some(&"{"))
+            | ("enum", Some(name), Some(&"{"))
+            | ("service", Some(name), Some(&"{")) => {
+                let mut qualified: Vec<&str> = file.package.iter().map(String::as_str).collect();
+                qualified.extend(scopes.iter().flatten().map(String::as_str));
+                qualified.push(name);
+                let qualified = qualified.join(".");
+                file.definitions.push(qualified.clone());
+                scopes.push(Some(qualified.rsplit('.').next().unwrap().to_owned()));
+                i += 3;
+            }
+            ("{", _, _) => {
+                scopes.push(None);
+                i += 1;
+            }
+            ("}", _, _) => {
+                scopes.pop();
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+
+    file
+}
+
+/// Where the path of each file `contents` imports is, leaving out the quotes.
+pub fn imports(contents: &str) -> Vec<Range<usize>> {
+    let tokens = tokenize(contents);
+    let mut imports = Vec::new();
+    let mut depth = 0;
+
+    for (i, &(_, token)) in tokens.iter().enumerate() {
+        match token {
+            "{" => depth += 1,
+            "}" => depth -= 1,
+            "import" if depth == 0 => {
+                let mut path = tokens.get(i + 1);
+                if let Some((_, "public")) | Some((_, "weak")) = path {
+                    path = tokens.get(i + 2);
+                }
+                if let Some(&(start, path)) = path {
+                    if path.len() >= 2 && (path.starts_with('"') || path.starts_with('\'')) {
+                        imports.push(start + 1..start + path.len() - 1);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    imports
+}
+
+/// Sets the file option `name` to `value`, written as it should appear in the file, or
+/// removes it when `value` is `None`. A new option goes after the package declaration.
+/// Options of messages, fields and the like are left alone.
+pub fn set_option(contents: &str, name: &str, value: Option<&str>) -> String {
+    let tokens = tokenize(contents);
+    let statements = file_statements(&tokens);
+    let line = value.map(|value| format!("option {} = {};\n", name, value));
+
+    let existing = statements.iter().find(|statement| {
+        let equals = statement.iter().position(|&(_, token)| token == "=");
+        statement[0].1 == "option"
+            && equals.map_or(false, |equals| {
+                statement[1..equals]
+                    .iter()
+                    .map(|&(_, token)| token)
+                    .collect::<String>()
+                    == name
+            })
+    });
+    if let Some(statement) = existing {
+        let (span, whole_line) = statement_span(contents, statement);
+        let replacement = match &line {
+            Some(line) if !whole_line => line.trim_end(),
+            Some(line) => line,
+            None => "",
+        };
+        return format!(
+            "{}{}{}",
+            &contents[..span.start],
+            replacement,
+            &contents[span.end..]
+        );
+    }
+
+    match line {
+        None => contents.to_owned(),
+        Some(line) => {
+            let declaration = |keyword: &str| {
+                statements
+                    .iter()
+                    .find(|statement| statement[0].1 == keyword)
+                    .map(|statement| statement_span(contents, statement).0.end)
+            };
+            let at = declaration("package")
+                .or_else(|| declaration("syntax"))
+                .unwrap_or(0);
+            let separator = if at == 0 || contents[..at].ends_with('\n') {
+                ""
+            } else {
+                "\n"
+            };
+            format!(
+                "{}{}{}{}",
+                &contents[..at],
+                separator,
+                line,
+                &contents[at..]
+            )
+        }
+    }
+}
+
+/// Quotes `text` as a string literal, escaping anything that would end it early.
+pub fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_ascii_control() => quoted.push_str(&format!("\\x{:02x}", c as u8)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The tokens of each `syntax`, `package` and `option` statement outside any block, up
+/// to and including its `;`.
+fn file_statements<'t, 'c>(tokens: &'t [(usize, &'c str)]) -> Vec<&'t [(usize, &'c str)]> {
+    let mut statements = Vec::new();
+    let mut depth = 0usize;
+
+    let mut i = 0;
+    while i < tokens.len() {
+        match tokens[i].1 {
+            "{" => depth += 1,
+            "}" => depth = depth.saturating_sub(1),
+            "syntax" | "package" | "option" if depth == 0 => {
+                // Aggregate option values may hold braces and semicolons of their own
+                let mut nested = 0usize;
+                let end = tokens[i..].iter().position(|&(_, token)| match token {
+                    "{" => {
+                        nested += 1;
+                        false
+                    }
+                    "}" => {
+                        nested = nested.saturating_sub(1);
+                        false
+                    }
+                    ";" => nested == 0,
+                    _ => false,
+                });
+                if let Some(end) = end {
+                    statements.push(&tokens[i..=i + end]);
+                    i += end;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    statements
+}
+
+/// Where `statement` is in `contents`, and whether that is its whole line: the statement
+/// is the only thing on it besides indentation, whitespace and a trailing comment.
+fn statement_span(contents: &str, statement: &[(usize, &str)]) -> (Range<usize>, bool) {
+    let (start, _) = statement[0];
+    let end = statement
+        .last()
+        .map(|&(at, token)| at + token.len())
+        .unwrap();
+
+    let line_start = contents[..start]
+        .rfind('\n')
+        .map_or(0, |newline| newline + 1);
+    let line_end = contents[end..]
+        .find('\n')
+        .map_or(contents.len(), |newline| end + newline + 1);
+    let indentation = &contents[line_start..start];
+    let rest = contents[end..line_end].trim();
+
+    if indentation.trim().is_empty() && (rest.is_empty() || rest.starts_with("//")) {
+        (line_start..line_end, true)
+    } else {
+        (start..end, false)
+    }
+}
+
+/// Splits `contents` into identifiers, string literals and single character symbols, each
+/// with where it starts, dropping comments and whitespace.
+fn tokenize(contents: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    let bytes = contents.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += 2;
+                while i < bytes.len() && !bytes[i..].starts_with(b"*/") {
+                    i += 1;
+                }
+                i += 2;
+            }
+            quote @ b'"' | quote @ b'\'' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                i += 1;
+                tokens.push((start, &contents[start..i.min(bytes.len())]));
+            }
+            c if c.is_ascii_alphanumeric() || c == b'_' || c == b'.' => {
+                while i < bytes.len()
+                    && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] == b'.')
+                {
+                    i += 1;
+                }
+                tokens.push((start, &contents[start..i]));
+            }
+            c if c.is_ascii_whitespace() => i += 1,
+            _ => {
+                // Symbols are ASCII, anything else is skipped a whole character at a time
+                i += contents[i..].chars().next().map_or(1, char::len_utf8);
+                tokens.push((start, &contents[start..i]));
+            }
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_package_and_definitions() {
+        let contents = r#"
+            syntax = "proto3";
+            // message Commented {
+            package acme.api.v1;
+            import "google/protobuf/timestamp.proto";
+
+            /* service AlsoCommented { */
+            message Price {
+                option (acme.message_option) = { name: "message Fake {" };
+                message Amount {
+                    enum Currency { GBP = 0; }
+                }
+                oneof value { Amount amount = 1; }
+                map<string, Amount> others = 2;
+            }
+
+            enum Status { UNKNOWN = 0; }
+
+            service Pricing {
+                rpc Quote(Price) returns (Price) { option deprecated = true; }
+            }
+        "#;
+
+        assert_eq!(
+            ProtoFile {
+                package: Some(String::from("acme.api.v1")),
+                definitions: vec![
+                    String::from("acme.api.v1.Price"),
+                    String::from("acme.api.v1.Price.Amount"),
+                    String::from("acme.api.v1.Price.Amount.Currency"),
+                    String::from("acme.api.v1.Status"),
+                    String::from("acme.api.v1.Pricing"),
+                ],
+            },
+            parse(contents)
+        );
+    }
+
+    #[test]
+    fn test_parses_files_without_package() {
+        let file = parse("message Empty {}\n");
+
+        assert_eq!(None, file.package);
+        assert_eq!(vec![String::from("Empty")], file.definitions);
+    }
+
+    #[test]
+    fn test_finds_imports_outside_comments() {
+        let contents = r#"
+            syntax = "proto3";
+            import "acme/types.proto";
+            import public 'acme/public.proto';
+            // import "acme/line_comment.proto";
+            /* import "acme/block_comment.proto"; */
+            import weak "acme/weak.proto"; // Trailing
+            message Price { string import = 1; }
+        "#;
+
+        assert_eq!(
+            vec!["acme/types.proto", "acme/public.proto", "acme/weak.proto"],
+            imports(contents)
+                .into_iter()
+                .map(|path| &contents[path])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_sets_options() {
+        let contents = "syntax = \"proto3\";\npackage acme.api.v1;\n\noption go_package = \"github.com/acme/api\"; // Theirs\noption java_multiple_files = true;\n\nmessage Price {}\n";
+
+        assert_eq!(
+            "syntax = \"proto3\";\npackage acme.api.v1;\n\noption go_package = \"example.com/ours\";\noption java_multiple_files = true;\n\nmessage Price {}\n",
+            set_option(contents, "go_package", Some("\"example.com/ours\""))
+        );
+        assert_eq!(
+            "syntax = \"proto3\";\npackage acme.api.v1;\n\noption go_package = \"github.com/acme/api\"; // Theirs\n\nmessage Price {}\n",
+            set_option(contents, "java_multiple_files", None)
+        );
+        assert_eq!(
+            "syntax = \"proto3\";\npackage acme.api.v1;\noption csharp_namespace = \"Acme.Api\";\n\noption go_package = \"github.com/acme/api\"; // Theirs\noption java_multiple_files = true;\n\nmessage Price {}\n",
+            set_option(contents, "csharp_namespace", Some("\"Acme.Api\""))
+        );
+        assert_eq!(contents, set_option(contents, "java_package", None));
+    }
+
+    #[test]
+    fn test_leaves_options_inside_blocks_alone() {
+        let contents = "package acme.api.v1;\n/* option deprecated = true; */\nmessage Price {\n  option deprecated = true;\n  int64 units = 1 [deprecated = true];\n}\nenum Status {\n  option deprecated = true;\n  UNKNOWN = 0;\n}\n";
+
+        assert_eq!(
+            "package acme.api.v1;\noption deprecated = false;\n/* option deprecated = true; */\nmessage Price {\n  option deprecated = true;\n  int64 units = 1 [deprecated = true];\n}\nenum Status {\n  option deprecated = true;\n  UNKNOWN = 0;\n}\n",
+            set_option(contents, "deprecated", Some("false"))
+        );
+        assert_eq!(contents, set_option(contents, "deprecated", None));
+    }
+
+    #[test]
+    fn test_sets_options_sharing_a_line() {
+        let contents =
+            "package acme.api.v1; option (acme.owner).team = \"pricing\"; message Price {}\n";
+
+        assert_eq!(
+            "package acme.api.v1; option (acme.owner).team = \"quotes\"; message Price {}\n",
+            set_option(contents, "(acme.owner).team", Some("\"quotes\""))
+        );
+        assert_eq!(
+            "package acme.api.v1;  message Price {}\n",
+            set_option(contents, "(acme.owner).team", None)
+        );
+    }
+
+    #[test]
+    fn test_quotes_text() {
+        assert_eq!(r#""com.acme""#, quote("com.acme"));
+        assert_eq!(
+            r#""a \"b\" \\ c\n; option x = 1;\x00""#,
+            quote("a \"b\" \\ c\n; option x = 1;\0")
+        );
+    }
+
+    #[test]
+    fn test_adds_options_without_package() {
+        assert_eq!(
+            "syntax = \"proto3\";\noption java_package = \"com.acme\";\nmessage Empty {}\n",
+            set_option(
+                "syntax = \"proto3\";\nmessage Empty {}\n",
+                "java_package",
+                Some("\"com.acme\"")
+            )
+        );
+        assert_eq!(
+            "option java_package = \"com.acme\";\nmessage Empty {}\n",
+            set_option("message Empty {}\n", "java_package", Some("\"com.acme\""))
+        );
+    }
+}
@@ -0,0 +1,309 @@
+/*
+ * Copyright 2020 Skyscanner Limited.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use crate::Result;
+use failure::{format_err, Error};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::env;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::Duration;
+
+pub const SETTINGS_ENV_VAR: &str = "PROTOVEND_SETTINGS";
+
+lazy_static! {
+    static ref SETTINGS: RwLock<Settings> = RwLock::new(Settings::default());
+}
+
+/// Machine-level settings shared by every project, read from `$PROTOVEND_SETTINGS`
+/// or `~/.protovend/settings.yml`.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub git: GitSettings,
+    pub url_rewrites: Vec<UrlRewrite>,
+    /// Hosts every project on this machine may vendor from, all of them when empty.
+    pub allowed_hosts: Vec<String>,
+    /// Set by `--allow-any-host` to ignore every `allowed_hosts`.
+    #[serde(skip)]
+    pub allow_any_host: bool,
+}
+
+/// Fetches remotes starting with `instead_of` from `url` instead, like git's
+/// `url.<base>.insteadOf`. Only the connection uses the rewritten URL; the lock file
+/// and logs keep the original.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UrlRewrite {
+    pub url: String,
+    pub instead_of: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitSettings {
+    pub backend: Backend,
+    pub clone: NetworkSettings,
+    pub fetch: NetworkSettings,
+    pub ls_remote: NetworkSettings,
+}
+
+/// How protovend talks to git remotes.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// The `git` executable, honouring the user's git configuration.
+    Cli,
+    /// The bundled libgit2, which needs no `git` executable.
+    Library,
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "cli" => Ok(Backend::Cli),
+            "library" => Ok(Backend::Library),
+            _ => Err(format_err!(
+                "Unknown git backend {}, expected cli or library",
+                s
+            )),
+        }
+    }
+}
+
+/// Timeout and retry policy for a single kind of git network operation.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkSettings {
+    /// Seconds before an attempt is abandoned, 0 waits forever.
+    pub timeout: u64,
+    /// Additional attempts made after a transient failure.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every further retry.
+    pub backoff_ms: u64,
+}
+
+impl Default for GitSettings {
+    fn default() -> Self {
+        GitSettings {
+            backend: Backend::Cli,
+            clone: NetworkSettings {
+                timeout: 600,
+                ..NetworkSettings::default()
+            },
+            fetch: NetworkSettings::default(),
+            ls_remote: NetworkSettings {
+                timeout: 60,
+                ..NetworkSettings::default()
+            },
+        }
+    }
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        NetworkSettings {
+            timeout: 300,
+            retries: 2,
+            backoff_ms: 1000,
+        }
+    }
+}
+
+impl NetworkSettings {
+    pub fn timeout(&self) -> Option<Duration> {
+        if self.timeout == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(self.timeout))
+        }
+    }
+
+    /// Delay before retry number `retry`, counting from 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u64.saturating_pow(retry.saturating_sub(1));
+        Duration::from_millis(self.backoff_ms.saturating_mul(factor))
+    }
+}
+
+impl Settings {
+    /// The URL to connect to for `url`, using the longest matching rewrite if any.
+    pub fn rewrite_url(&self, url: &str) -> String {
+        self.url_rewrites
+            .iter()
+            .filter(|rewrite| url.starts_with(&rewrite.instead_of))
+            .max_by_key(|rewrite| rewrite.instead_of.len())
+            .map(|rewrite| format!("{}{}", rewrite.url, &url[rewrite.instead_of.len()..]))
+            .unwrap_or_else(|| url.to_owned())
+    }
+
+    /// Applies command line overrides to every git network operation.
+    pub fn with_overrides(mut self, timeout: Option<u64>, retries: Option<u32>) -> Self {
+        for network in [
+            &mut self.git.clone,
+            &mut self.git.fetch,
+            &mut self.git.ls_remote,
+        ] {
+            if let Some(timeout) = timeout {
+                network.timeout = timeout;
+            }
+            if let Some(retries) = retries {
+                network.retries = retries;
+            }
+        }
+        self
+    }
+}
+
+/// Loads the settings file if there is one, falling back to defaults otherwise.
+pub fn load() -> Result<Settings> {
+    match settings_path() {
+        Some(path) if path.is_file() => load_settings(&path),
+        _ => Ok(Settings::default()),
+    }
+}
+
+/// Makes `settings` the ones used by every subsequent operation.
+pub fn configure(settings: Settings) {
+    *SETTINGS.write().unwrap() = settings;
+}
+
+pub(crate) fn current() -> Settings {
+    SETTINGS.read().unwrap().clone()
+}
+
+fn settings_path() -> Option<PathBuf> {
+    env::var_os(SETTINGS_ENV_VAR)
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME")
+                .or_else(|| env::var_os("USERPROFILE"))
+                .map(|home| Path::new(&home).join(".protovend/settings.yml"))
+        })
+}
+
+fn load_settings(path: &Path) -> Result<Settings> {
+    let f = File::open(path)?;
+    serde_yaml::from_reader(f)
+        .map_err(|e| format_err!("Invalid settings file {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+
+    #[test]
+    fn test_correctly_parses_settings() {
+        let settings_contents = "--- \
+             \ngit: \
+             \n  backend: library \
+             \n  fetch: \
+             \n    timeout: 30 \
+             \n    retries: 5";
+
+        let settings_path =
+            tests_utils::fs::write_contents_to_temp_file(settings_contents, "settings");
+
+        let actual_settings = load_settings(&settings_path).unwrap();
+
+        assert_eq!(Backend::Library, actual_settings.git.backend);
+        assert_eq!(GitSettings::default().clone, actual_settings.git.clone);
+        assert_eq!(
+            NetworkSettings {
+                timeout: 30,
+                retries: 5,
+                backoff_ms: 1000,
+            },
+            actual_settings.git.fetch
+        );
+    }
+
+    #[test]
+    fn test_overrides_apply_to_every_operation() {
+        let settings = Settings::default().with_overrides(Some(0), Some(7));
+
+        for network in &[
+            settings.git.clone,
+            settings.git.fetch,
+            settings.git.ls_remote,
+        ] {
+            assert_eq!(None, network.timeout());
+            assert_eq!(7, network.retries);
+        }
+    }
+
+    #[test]
+    fn test_rewrites_longest_matching_prefix() {
+        let settings_contents = "--- \
+             \nurl_rewrites: \
+             \n  - url: https://mirror.internal/github/ \
+             \n    instead_of: https://github.com/ \
+             \n  - url: https://mirror.internal/googleapis/ \
+             \n    instead_of: https://github.com/googleapis/";
+
+        let settings_path =
+            tests_utils::fs::write_contents_to_temp_file(settings_contents, "rewrite_settings");
+
+        let settings = load_settings(&settings_path).unwrap();
+
+        assert_eq!(
+            "https://mirror.internal/github/user/project.git",
+            settings.rewrite_url("https://github.com/user/project.git")
+        );
+        assert_eq!(
+            "https://mirror.internal/googleapis/googleapis.git",
+            settings.rewrite_url("https://github.com/googleapis/googleapis.git")
+        );
+        assert_eq!(
+            "git@github.com:user/project.git",
+            settings.rewrite_url("git@github.com:user/project.git")
+        );
+    }
+
+    #[test]
+    fn test_correctly_parses_allowed_hosts() {
+        let settings_contents = "--- \
+             \nallowed_hosts: \
+             \n  - github.com \
+             \n  - \"*.internal.example.com\"";
+
+        let settings_path =
+            tests_utils::fs::write_contents_to_temp_file(settings_contents, "hosts_settings");
+
+        let settings = load_settings(&settings_path).unwrap();
+
+        assert_eq!(
+            vec!["github.com", "*.internal.example.com"],
+            settings.allowed_hosts
+        );
+        assert!(!settings.allow_any_host);
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        let network = NetworkSettings::default();
+
+        assert_eq!(Duration::from_secs(1), network.backoff(1));
+        assert_eq!(Duration::from_secs(2), network.backoff(2));
+        assert_eq!(Duration::from_secs(4), network.backoff(3));
+    }
+}
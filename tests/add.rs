@@ -15,7 +15,6 @@
 */
 
 use common::command;
-use tempfile;
 
 mod common;
 
@@ -58,6 +57,7 @@ fn test_add_with_init() {
 
     let expected_contents = String::from(
         "---\
+         \nversion: 2\
          \nmin_protovend_version: 4.2.0\
          \nvendor:\
          \n  - url: \"https://github.com/Skyscanner/protovend-test-protos.git\"\
@@ -87,6 +87,7 @@ fn test_add_two() {
     let status = command(&dir)
         .arg("add")
         .arg("https://github.com/Skyscanner/protovend-test-protos.git")
+        .arg("--branch=master")
         .status()
         .unwrap();
 
@@ -95,6 +96,7 @@ fn test_add_two() {
     let status = command(&dir)
         .arg("add")
         .arg("git@github.com:Skyscanner/protovend-test-protos-fake.git")
+        .arg("--branch=master")
         .arg("-d=src/proto")
         .arg("-p=path/to")
         .arg("-f=^(a|b)c$")
@@ -105,6 +107,7 @@ fn test_add_two() {
 
     let expected_contents = String::from(
         "---\
+         \nversion: 2\
          \nmin_protovend_version: 4.2.0\
          \nvendor:\
          \n  - url: \"git@github.com:Skyscanner/protovend-test-protos-fake.git\"\
@@ -141,6 +144,7 @@ fn test_add_with_different_proto_paths() {
     let status = command(&dir)
         .arg("add")
         .arg("git@github.com:Skyscanner/protovend-test-protos2.git")
+        .arg("--branch=master")
         .arg("-p=path1/to")
         .status()
         .unwrap();
@@ -150,6 +154,7 @@ fn test_add_with_different_proto_paths() {
     let status = command(&dir)
         .arg("add")
         .arg("git@github.com:Skyscanner/protovend-test-protos2.git")
+        .arg("--branch=master")
         .arg("-p=path2/to")
         .status()
         .unwrap();
@@ -158,6 +163,7 @@ fn test_add_with_different_proto_paths() {
 
     let expected_contents = String::from(
         "---\
+         \nversion: 2\
          \nmin_protovend_version: 4.2.0\
          \nvendor:\
          \n  - url: \"git@github.com:Skyscanner/protovend-test-protos2.git\"\
@@ -175,3 +181,99 @@ fn test_add_with_different_proto_paths() {
         dir.path().join(".protovend.yml").as_path(),
     );
 }
+
+#[test]
+fn test_add_without_git_suffix() {
+    let dir = tempfile::tempdir().unwrap();
+    let status = command(&dir).arg("init").status().unwrap();
+
+    assert!(status.success());
+
+    let status = command(&dir)
+        .arg("add")
+        .arg("https://github.com/googleapis/googleapis")
+        .arg("--branch=master")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+
+    let status = command(&dir)
+        .arg("add")
+        .arg("https://github.com/googleapis/googleapis.git")
+        .arg("--branch=master")
+        .arg("-p=google/api")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+
+    let expected_contents = String::from(
+        "---\
+         \nversion: 2\
+         \nmin_protovend_version: 4.2.0\
+         \nvendor:\
+         \n  - url: \"https://github.com/googleapis/googleapis\"\
+         \n    branch: master\
+         \n    proto_dir: proto\
+         \n    proto_paths:\
+         \n      - googleapis/googleapis\
+         \n      - google/api\
+         \n    filename_regex: \".*\"\
+         \n    resolve_dependency: false\n",
+    );
+
+    tests_utils::fs::assert_file_contents_eq(
+        expected_contents,
+        dir.path().join(".protovend.yml").as_path(),
+    );
+}
+
+#[test]
+fn test_add_refuses_hosts_not_allowed() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join(".protovend.yml"),
+        "---\nmin_protovend_version: 4.2.0\nvendor: []\nallowed_hosts:\n  - github.com\n",
+    )
+    .unwrap();
+
+    let status = command(&dir)
+        .arg("add")
+        .arg("https://gitlab.com/user/project.git")
+        .status()
+        .unwrap();
+
+    assert!(!status.success());
+
+    let status = command(&dir)
+        .arg("--allow-any-host")
+        .arg("add")
+        .arg("https://gitlab.com/user/project.git")
+        .arg("--branch=master")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+
+    let expected_contents = String::from(
+        "---\
+         \nversion: 2\
+         \nmin_protovend_version: 4.2.0\
+         \nvendor:\
+         \n  - url: \"https://gitlab.com/user/project.git\"\
+         \n    branch: master\
+         \n    proto_dir: proto\
+         \n    proto_paths:\
+         \n      - user/project\
+         \n    filename_regex: \".*\"\
+         \n    resolve_dependency: false\
+         \nallowed_hosts:\
+         \n  - github.com\n",
+    );
+
+    tests_utils::fs::assert_file_contents_eq(
+        expected_contents,
+        dir.path().join(".protovend.yml").as_path(),
+    );
+}
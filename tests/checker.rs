@@ -14,6 +14,8 @@
  * limitations under the License.
 */
 
+#![allow(dead_code)]
+
 use git2::Repository;
 use std::fs::{self, File};
 use std::io::Write;
@@ -15,7 +15,6 @@
 */
 
 use common::command;
-use tempfile;
 
 mod common;
 
@@ -35,7 +34,7 @@ fn test_init_fresh() {
     assert!(dir.path().join(".protovend.yml").exists());
 
     tests_utils::fs::assert_file_contents_eq(
-        String::from("---\nmin_protovend_version: 4.2.0\nvendor: []\n"),
+        String::from("---\nversion: 2\nmin_protovend_version: 4.2.0\nvendor: []\n"),
         dir.path().join(".protovend.yml").as_path(),
     );
 }
@@ -50,7 +49,7 @@ fn test_init_does_not_overwrite() {
 
     assert!(dir.join(".protovend.yml").exists());
 
-    let status = command(&dir).arg("init").status().unwrap();
+    let status = command(dir).arg("init").status().unwrap();
     assert!(status.success());
 
     tests_utils::fs::assert_file_contents_eq(
@@ -58,3 +57,37 @@ fn test_init_does_not_overwrite() {
         foobar_protovend_path.as_path(),
     )
 }
+
+#[test]
+fn test_init_stamps_lock_with_source_date_epoch() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let status = command(&dir)
+        .env("SOURCE_DATE_EPOCH", "1577894484")
+        .arg("init")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+
+    tests_utils::fs::assert_file_contents_eq(
+        String::from(
+            "---\nversion: 2\nimports: []\nmin_protovend_version: 4.2.0\nupdated: \"2020-01-01T16:01:24Z\"\n",
+        ),
+        dir.path().join(".protovend.lock").as_path(),
+    );
+}
+
+#[test]
+fn test_init_refuses_out_of_range_source_date_epoch() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let output = command(&dir)
+        .env("SOURCE_DATE_EPOCH", "9223372036854775807")
+        .arg("init")
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(1), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("SOURCE_DATE_EPOCH must be"));
+}
@@ -17,7 +17,6 @@
 use common::command;
 use std::fs::{self, File};
 use std::io::{Read, Write};
-use tempfile;
 use walkdir::WalkDir;
 
 mod common;
@@ -92,7 +91,7 @@ fn test_install_existing_legacy() {
 
     let dir = legacy_protovend_config_path.parent().unwrap();
 
-    let status = command(&dir).arg("install").status().unwrap();
+    let status = command(dir).arg("install").status().unwrap();
 
     assert!(status.success());
 
@@ -520,3 +519,591 @@ fn test_install_dependencies() {
         ]
     );
 }
+
+fn git(dir: &std::path::Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .current_dir(dir)
+        .args([
+            "-c",
+            "user.name=protovend",
+            "-c",
+            "user.email=p@example.com",
+        ])
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// A project depending on https://fake.test/acme/api.git, which is served from a local
+/// repository holding `protos` under proto/. Returns the project, the repository and the
+/// settings file that serves it.
+fn fake_project(
+    protos: &[(&str, &str)],
+    config: &str,
+) -> (tempfile::TempDir, tempfile::TempDir, std::path::PathBuf) {
+    let remote = tempfile::tempdir().unwrap();
+    for (path, contents) in protos {
+        let path = remote.path().join("proto").join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    git(remote.path(), &["init", "-q", "-b", "main"]);
+    git(remote.path(), &["add", "."]);
+    git(remote.path(), &["commit", "-q", "-m", "protos"]);
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join(".protovend.yml"),
+        format!(
+            "---\
+             \nversion: 2\
+             \nmin_protovend_version: 4.2.0\
+             \nvendor:\
+             \n  - url: https://fake.test/acme/api.git\
+             \n    branch: main\
+             \n    proto_dir: proto\
+             \n    proto_paths:\
+             \n      - acme/api\
+             \n    filename_regex: \".*\"\
+             \n    resolve_dependency: true\n{}",
+            config
+        ),
+    )
+    .unwrap();
+    let settings = dir.path().join("settings.yml");
+    fs::write(
+        &settings,
+        format!(
+            "url_rewrites:\n  - url: file://{}\n    instead_of: https://fake.test/acme/api.git\n",
+            remote.path().display()
+        ),
+    )
+    .unwrap();
+
+    (dir, remote, settings)
+}
+
+#[test]
+fn test_install_records_vendored_files() {
+    let (dir, _remote, settings) = fake_project(
+        &[
+            (
+                "acme/api/service.proto",
+                "syntax = \"proto3\";\nimport \"acme/common/types.proto\";\n",
+            ),
+            ("acme/api/health.proto", "syntax = \"proto3\";\n"),
+            (
+                "acme/common/types.proto",
+                "syntax = \"proto3\";\nimport \"acme/api/service.proto\";\n",
+            ),
+        ],
+        "",
+    );
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    assert!(dir
+        .path()
+        .join("third_party/protovend/acme/common/types.proto")
+        .exists());
+
+    let contents = fs::read_to_string(dir.path().join(".protovend.lock")).unwrap();
+    assert!(
+        contents.contains(
+            "    files:\
+             \n      - path: acme/api/health.proto\
+             \n      - path: acme/api/service.proto\
+             \n      - path: acme/common/types.proto\
+             \n        imported_by: acme/api/service.proto\n"
+        ),
+        "{}",
+        contents
+    );
+}
+
+#[test]
+fn test_install_checks_imports_resolve() {
+    let protos = [(
+        "acme/api/service.proto",
+        "syntax = \"proto3\";\nimport \"local/money.proto\";\n",
+    )];
+    let (dir, _remote, settings) = fake_project(&protos, "");
+
+    let output = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("P003"));
+
+    let (dir, _remote, settings) = fake_project(&protos, "strict_imports: true\n");
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(!status.success());
+    let lock = fs::read_to_string(dir.path().join(".protovend.lock")).unwrap();
+    assert!(!lock.contains("files:"), "{}", lock);
+
+    let (dir, _remote, settings) =
+        fake_project(&protos, "strict_imports: true\ninclude_roots:\n  - proto\n");
+    fs::create_dir_all(dir.path().join("proto/local")).unwrap();
+    fs::write(dir.path().join("proto/local/money.proto"), "").unwrap();
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+}
+
+#[test]
+fn test_install_checks_for_import_cycles() {
+    let protos = [
+        (
+            "acme/api/service.proto",
+            "syntax = \"proto3\";\nimport \"acme/api/types.proto\";\n",
+        ),
+        (
+            "acme/api/types.proto",
+            "syntax = \"proto3\";\nimport \"acme/api/service.proto\";\n",
+        ),
+    ];
+    let (dir, _remote, settings) = fake_project(&protos, "strict_imports: true\n");
+
+    let output = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("P004"));
+}
+
+#[test]
+fn test_install_vendors_only_what_roots_need() {
+    let protos = [
+        (
+            "acme/api/pricing.proto",
+            "syntax = \"proto3\";\
+             \npackage acme.api;\
+             \nimport \"acme/api/money.proto\";\
+             \nservice Pricing { rpc Quote(Money) returns (Money); }\n",
+        ),
+        (
+            "acme/api/money.proto",
+            "syntax = \"proto3\";\npackage acme.api;\nmessage Money {}\n",
+        ),
+        (
+            "acme/api/unused.proto",
+            "syntax = \"proto3\";\npackage acme.api;\nmessage Unused {}\n",
+        ),
+    ];
+    let (dir, _remote, settings) = fake_project(&protos, "    roots:\n      - acme.api.Pricing\n");
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    let vendored = dir.path().join("third_party/protovend/acme/api");
+    assert!(vendored.join("pricing.proto").exists());
+    assert!(vendored.join("money.proto").exists());
+    assert!(!vendored.join("unused.proto").exists());
+
+    let config_path = dir.path().join(".protovend.yml");
+    let config = fs::read_to_string(&config_path).unwrap();
+    fs::write(
+        &config_path,
+        config.replace("acme.api.Pricing", "acme.api.Unused"),
+    )
+    .unwrap();
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    assert!(!vendored.join("pricing.proto").exists());
+    assert!(vendored.join("unused.proto").exists());
+    let lock = fs::read_to_string(dir.path().join(".protovend.lock")).unwrap();
+    assert!(lock.contains("acme.api.Unused"), "{}", lock);
+
+    let (dir, _remote, settings) = fake_project(&protos, "    roots:\n      - acme.api.Missing\n");
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(!status.success());
+}
+
+#[test]
+fn test_install_filters_by_package() {
+    let protos = [
+        (
+            "acme/api/payment.proto",
+            "syntax = \"proto3\";\
+             \npackage acme.payments.v1;\
+             \nimport \"acme/api/money.proto\";\n",
+        ),
+        (
+            "acme/api/money.proto",
+            "syntax = \"proto3\";\npackage acme.money;\n",
+        ),
+        (
+            "acme/api/refund.proto",
+            "syntax = \"proto3\";\npackage acme.refunds.v1;\n",
+        ),
+    ];
+    let (dir, _remote, settings) =
+        fake_project(&protos, "    packages:\n      - acme.payments.*\n");
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    let vendored = dir.path().join("third_party/protovend/acme/api");
+    assert!(vendored.join("payment.proto").exists());
+    // Imported from a matching file, so vendored with resolve_dependency
+    assert!(vendored.join("money.proto").exists());
+    assert!(!vendored.join("refund.proto").exists());
+
+    let config_path = dir.path().join(".protovend.yml");
+    let config = fs::read_to_string(&config_path).unwrap();
+    fs::write(
+        &config_path,
+        config.replace("acme.payments.*", "acme.refunds.*"),
+    )
+    .unwrap();
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    assert!(!vendored.join("payment.proto").exists());
+    assert!(vendored.join("refund.proto").exists());
+}
+
+#[test]
+fn test_install_rewrites_options() {
+    let protos = [(
+        "acme/api/payment.proto",
+        "syntax = \"proto3\";\
+         \npackage acme.payments.v1;\
+         \noption go_package = \"github.com/acme/api/payments\";\
+         \noption java_multiple_files = true;\n",
+    )];
+    let (dir, _remote, settings) = fake_project(
+        &protos,
+        "    options:\
+         \n      go_package: \"example.com/gen/{path}\"\
+         \n      java_package: \"com.example.{package}\"\
+         \n      java_multiple_files: ~\n",
+    );
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    tests_utils::fs::assert_file_contents_eq(
+        String::from(
+            "syntax = \"proto3\";\
+             \npackage acme.payments.v1;\
+             \noption java_package = \"com.example.acme.payments.v1\";\
+             \noption go_package = \"example.com/gen/acme/api\";\n",
+        ),
+        &dir.path()
+            .join("third_party/protovend/acme/api/payment.proto"),
+    );
+
+    let config_path = dir.path().join(".protovend.yml");
+    let config = fs::read_to_string(&config_path).unwrap();
+    fs::write(
+        &config_path,
+        config.replace(
+            "java_multiple_files: ~",
+            "java_multiple_files: ~\n      optimize_for:\n        enum: SPEED",
+        ),
+    )
+    .unwrap();
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    let contents = fs::read_to_string(
+        dir.path()
+            .join("third_party/protovend/acme/api/payment.proto"),
+    )
+    .unwrap();
+    assert!(
+        contents.contains("\noption optimize_for = SPEED;\n"),
+        "{}",
+        contents
+    );
+}
+
+#[test]
+fn test_install_relocates_files_and_their_imports() {
+    let protos = [
+        (
+            "acme/api/payment.proto",
+            "syntax = \"proto3\";\
+             \npackage acme.payments.v1;\
+             \nimport \"acme/api/money.proto\";\n",
+        ),
+        (
+            "acme/api/money.proto",
+            "syntax = \"proto3\";\npackage acme.money;\n",
+        ),
+    ];
+    let (dir, _remote, settings) =
+        fake_project(&protos, "    relocate:\n      acme/api: vendor/acme/api\n");
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    // Import checks pass, as imports point where the files were vendored
+    assert!(status.success());
+    let vendored = dir.path().join("third_party/protovend");
+    assert!(!vendored.join("acme").exists());
+    assert!(vendored.join("vendor/acme/api/money.proto").exists());
+    tests_utils::fs::assert_file_contents_eq(
+        String::from(
+            "syntax = \"proto3\";\
+             \npackage acme.payments.v1;\
+             \nimport \"vendor/acme/api/money.proto\";\n",
+        ),
+        &vendored.join("vendor/acme/api/payment.proto"),
+    );
+    let lock = fs::read_to_string(dir.path().join(".protovend.lock")).unwrap();
+    assert!(
+        lock.contains("- path: vendor/acme/api/payment.proto"),
+        "{}",
+        lock
+    );
+
+    let config_path = dir.path().join(".protovend.yml");
+    let config = fs::read_to_string(&config_path).unwrap();
+    fs::write(
+        &config_path,
+        config.replace("vendor/acme/api", "acme/payments"),
+    )
+    .unwrap();
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    assert!(!vendored.join("vendor").exists());
+    assert!(vendored.join("acme/payments/money.proto").exists());
+}
+
+#[test]
+fn test_install_adds_provenance_headers() {
+    let protos = [
+        (
+            "acme/api/payment.proto",
+            "syntax = \"proto3\";\nimport \"acme/api/money.proto\";\n",
+        ),
+        ("acme/api/money.proto", "syntax = \"proto3\";\n"),
+    ];
+    let (dir, remote, settings) = fake_project(&protos, "provenance_headers: true\n");
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    let head = std::process::Command::new("git")
+        .current_dir(remote.path())
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .unwrap();
+    tests_utils::fs::assert_file_contents_eq(
+        format!(
+            "// Code generated by protovend. DO NOT EDIT.\
+             \n// Managed by protovend, run protovend install or update to change it.\
+             \n// source: https://fake.test/acme/api.git\
+             \n// commit: {}\
+             \n// path: proto/acme/api/payment.proto\
+             \n\
+             \nsyntax = \"proto3\";\
+             \nimport \"acme/api/money.proto\";\n",
+            String::from_utf8(head.stdout).unwrap().trim()
+        ),
+        &dir.path()
+            .join("third_party/protovend/acme/api/payment.proto"),
+    );
+
+    // The header doesn't get in the way of reading imports from vendored files
+    let output = command(&dir).arg("graph").output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("\"acme/api/payment.proto\" -> \"acme/api/money.proto\""));
+}
+
+#[test]
+fn test_install_vendors_license() {
+    let (dir, remote, settings) =
+        fake_project(&[("acme/api/payment.proto", "syntax = \"proto3\";\n")], "");
+    fs::write(
+        remote.path().join("LICENSE"),
+        "MIT License\n\nPermission is hereby granted, free of charge, to any person\n",
+    )
+    .unwrap();
+    fs::write(remote.path().join("README.md"), "# Acme API\n").unwrap();
+    fs::create_dir_all(remote.path().join("legal")).unwrap();
+    fs::write(
+        remote.path().join("legal/NOTICE.txt"),
+        "SPDX-License-Identifier: Apache-2.0\n",
+    )
+    .unwrap();
+    git(remote.path(), &["add", "."]);
+    git(remote.path(), &["commit", "-q", "-m", "license"]);
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    let vendored = dir.path().join("third_party/protovend");
+    assert!(vendored
+        .join("licenses/fake.test/acme/api/https/LICENSE")
+        .exists());
+    assert!(vendored
+        .join("licenses/fake.test/acme/api/https/README.md")
+        .exists());
+    assert!(!vendored.join("acme/api/LICENSE").exists());
+    let lock = fs::read_to_string(dir.path().join(".protovend.lock")).unwrap();
+    assert!(
+        lock.contains(
+            "    license:\
+             \n      path: LICENSE\
+             \n      vendored: licenses/fake.test/acme/api/https/LICENSE\
+             \n      spdx: MIT\
+             \n    readme: licenses/fake.test/acme/api/https/README.md\n"
+        ),
+        "{}",
+        lock
+    );
+
+    let output = command(&dir).arg("licenses").output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("https://fake.test/acme/api.git: MIT in LICENSE"));
+
+    let mut config = fs::OpenOptions::new()
+        .append(true)
+        .open(dir.path().join(".protovend.yml"))
+        .unwrap();
+    writeln!(config, "    license_file: legal/NOTICE.txt").unwrap();
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    assert!(vendored
+        .join("licenses/fake.test/acme/api/https/NOTICE.txt")
+        .exists());
+    let lock = fs::read_to_string(dir.path().join(".protovend.lock")).unwrap();
+    assert!(lock.contains("spdx: Apache-2.0"), "{}", lock);
+}
+
+#[test]
+fn test_install_vendors_licenses_of_the_same_repository_apart() {
+    // The same repository over ssh, and at a ref, each keep a license of their own
+    let (dir, remote, settings) = fake_project(
+        &[("acme/api/payment.proto", "syntax = \"proto3\";\n")],
+        "  - url: ssh://git@fake.test/acme/api.git\
+         \n    branch: main\
+         \n    proto_dir: proto\
+         \n    proto_paths:\
+         \n      - acme/api\
+         \n    filename_regex: \".*\"\
+         \n    resolve_dependency: false\
+         \n  - url: https://fake.test/acme/api.git#main\
+         \n    branch: main\
+         \n    proto_dir: proto\
+         \n    proto_paths:\
+         \n      - acme/api\
+         \n    filename_regex: \".*\"\
+         \n    resolve_dependency: false\n",
+    );
+    fs::write(remote.path().join("LICENSE"), "MIT License\n").unwrap();
+    git(remote.path(), &["add", "."]);
+    git(remote.path(), &["commit", "-q", "-m", "license"]);
+    let mut rewrites = fs::OpenOptions::new().append(true).open(&settings).unwrap();
+    writeln!(
+        rewrites,
+        "  - url: file://{}\n    instead_of: ssh://git@fake.test/acme/api.git",
+        remote.path().display()
+    )
+    .unwrap();
+
+    let status = command(&dir)
+        .env("PROTOVEND_SETTINGS", &settings)
+        .arg("install")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    let licenses = dir
+        .path()
+        .join("third_party/protovend/licenses/fake.test/acme/api");
+    for dependency in &["https", "ssh", "https@main"] {
+        assert!(
+            licenses.join(dependency).join("LICENSE").exists(),
+            "{}",
+            dependency
+        );
+    }
+}
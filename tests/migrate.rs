@@ -0,0 +1,82 @@
+/*
+ * Copyright 2020 Skyscanner Limited.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use common::command;
+use std::fs;
+
+mod common;
+
+#[test]
+fn test_migrate_rewrites_unversioned_files() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join(".protovend.yml"),
+        "---\
+         \nmin_protovend_version: 0.1.8\
+         \nvendor:\
+         \n  - url: https://github.com/user/project.git\
+         \n    branch: master\
+         \n    proto_dir: proto\
+         \n    proto_paths:\
+         \n      - path/to\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join(".protovend.lock"),
+        "---\
+         \nimports:\
+         \n  - branch: master\
+         \n    commit: a9fef901ae63f689a4180bf8255d16a45baf04a1\
+         \n    url: https://github.com/user/project.git\
+         \n    proto_dir: proto\
+         \n    proto_paths:\
+         \n      - path/to\
+         \nmin_protovend_version: 0.1.8\
+         \nupdated: \"2019-11-20 15:02:12.330896\"\n",
+    )
+    .unwrap();
+
+    let status = command(&dir).arg("migrate").status().unwrap();
+
+    assert!(status.success());
+
+    let config = fs::read_to_string(dir.path().join(".protovend.yml")).unwrap();
+    assert!(config.starts_with("---\nversion: 2\n"), "{}", config);
+    assert!(config.contains("filename_regex: \".*\""));
+
+    let lock = fs::read_to_string(dir.path().join(".protovend.lock")).unwrap();
+    assert!(lock.starts_with("---\nversion: 2\n"), "{}", lock);
+    assert!(lock.contains("commit: a9fef901ae63f689a4180bf8255d16a45baf04a1"));
+    assert!(lock.contains("updated: \"2019-11-20T15:02:12Z\""));
+}
+
+#[test]
+fn test_migrate_refuses_newer_versions() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join(".protovend.yml"),
+        "---\nversion: 99\nmin_protovend_version: 0.1.8\nvendor: []\n",
+    )
+    .unwrap();
+
+    let status = command(&dir).arg("migrate").status().unwrap();
+
+    assert!(!status.success());
+    assert_eq!(
+        "---\nversion: 99\nmin_protovend_version: 0.1.8\nvendor: []\n",
+        fs::read_to_string(dir.path().join(".protovend.yml")).unwrap()
+    );
+}
@@ -17,7 +17,6 @@
 use common::command;
 use std::fs::{self, File};
 use std::io::{Read, Write};
-use tempfile;
 
 mod common;
 